@@ -1,5 +1,5 @@
 // wasm version from node_modules
-import init, {reduce} from "mediancut-wasm/mediancut_wasm";
+import init, {reduce_with_steps} from "mediancut-wasm/mediancut_wasm";
 // ts version from node_modules
 // import { calculateColorCount, reduce as reduceTs } from '../../../../../../packages/mediancut/lib/mediancut';
 // from cdn
@@ -10,16 +10,18 @@ import init, {reduce} from "mediancut-wasm/mediancut_wasm";
 (async () => {
   await init();
   self.addEventListener('message', ({ data }) => {
-    const res = reduce(data.imageData.data, data.size);
+    // 減色と分割の過程を1回の減色で受け取る
+    const res = reduce_with_steps(data.imageData.data, data.size);
+    const bucketsPerStep = JSON.parse(res.steps);
 
     const imageData = new ImageData(
-      new Uint8ClampedArray(res),
+      new Uint8ClampedArray(res.data),
       data.imageData.width,
       data.imageData.height,
     )
     // eslint-disable-next-line @typescript-eslint/ban-ts-comment
     // @ts-ignore
-    self.postMessage({ imageData, bucketsPerStep: [] }, [
+    self.postMessage({ imageData, bucketsPerStep }, [
       imageData.data.buffer,
     ]);
   });
@@ -0,0 +1,165 @@
+// 変換行列は元の論文の値をそのまま使う
+#![allow(clippy::excessive_precision)]
+
+use crate::histogram::Colors;
+use crate::options::ColorSpace;
+
+// OKLabとCIELABの値をsRGB(0〜255)と同じくらいの範囲にするための係数
+const OKLAB_SCALE: f32 = 255.0;
+const LAB_L_SCALE: f32 = 2.55;
+
+/// sRGB(0〜255)をリニア(0〜1)にする
+pub(crate) fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// リニア(0〜1)をsRGB(0〜255)にする
+pub(crate) fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let value = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (value * 255.0).round() as u8
+}
+
+fn linear_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+fn oklab_to_linear([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let l_ = (l + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m_ = (l - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s_ = (l - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+    [
+        4.0767416621 * l_ - 3.3077115913 * m_ + 0.2309699292 * s_,
+        -1.2684380046 * l_ + 2.6097574011 * m_ - 0.3413193965 * s_,
+        -0.0041960863 * l_ - 0.7034186147 * m_ + 1.7076147010 * s_,
+    ]
+}
+
+// CIELABは白色点をD65とする
+const WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+const DELTA: f32 = 6.0 / 29.0;
+
+fn linear_to_lab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let x = 0.4124564 * r + 0.3575761 * g + 0.1804375 * b;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z = 0.0193339 * r + 0.1191920 * g + 0.9503041 * b;
+    let f = |t: f32| {
+        if t > DELTA * DELTA * DELTA {
+            t.cbrt()
+        } else {
+            t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+        }
+    };
+    let fx = f(x / WHITE[0]);
+    let fy = f(y / WHITE[1]);
+    let fz = f(z / WHITE[2]);
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn lab_to_linear([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let f = |t: f32| {
+        if t > DELTA {
+            t * t * t
+        } else {
+            3.0 * DELTA * DELTA * (t - 4.0 / 29.0)
+        }
+    };
+    let fy = (l + 16.0) / 116.0;
+    let x = WHITE[0] * f(fy + a / 500.0);
+    let y = WHITE[1] * f(fy);
+    let z = WHITE[2] * f(fy - b / 200.0);
+    [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ]
+}
+
+impl ColorSpace {
+    /// 色をこの色空間の座標 [c0, c1, c2, a] にする
+    pub(crate) fn coords(self, color: &Colors) -> [f32; 4] {
+        let linear = || [srgb_to_linear(color.r), srgb_to_linear(color.g), srgb_to_linear(color.b)];
+        let [c0, c1, c2] = match self {
+            ColorSpace::Srgb => [color.r as f32, color.g as f32, color.b as f32],
+            ColorSpace::Oklab => linear_to_oklab(linear()).map(|v| v * OKLAB_SCALE),
+            ColorSpace::Lab => {
+                let [l, a, b] = linear_to_lab(linear());
+                [l * LAB_L_SCALE, a, b]
+            }
+        };
+        [c0, c1, c2, color.a as f32]
+    }
+
+    /// この色空間の座標をsRGBに戻す
+    pub(crate) fn to_srgb(self, [c0, c1, c2]: [f32; 3]) -> (u8, u8, u8) {
+        let linear = match self {
+            ColorSpace::Srgb => return (
+                c0.round().clamp(0.0, 255.0) as u8,
+                c1.round().clamp(0.0, 255.0) as u8,
+                c2.round().clamp(0.0, 255.0) as u8,
+            ),
+            ColorSpace::Oklab => oklab_to_linear([c0 / OKLAB_SCALE, c1 / OKLAB_SCALE, c2 / OKLAB_SCALE]),
+            ColorSpace::Lab => lab_to_linear([c0 / LAB_L_SCALE, c1, c2]),
+        };
+        (linear_to_srgb(linear[0]), linear_to_srgb(linear[1]), linear_to_srgb(linear[2]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_srgb_to_linear() {
+        assert_eq!(srgb_to_linear(0), 0.0);
+        assert_eq!(srgb_to_linear(255), 1.0);
+        assert!((srgb_to_linear(128) - 0.2158605).abs() < 0.00001);
+        for value in 0..=255 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+        }
+    }
+
+    #[test]
+    fn test_coords() {
+        let white = Colors::new(255, 255, 255, 255, 1);
+        let [l, a, b, alpha] = ColorSpace::Oklab.coords(&white);
+        assert!((l - 255.0).abs() < 0.01 && a.abs() < 0.01 && b.abs() < 0.01);
+        assert_eq!(alpha, 255.0);
+
+        let [l, a, b, _alpha] = ColorSpace::Lab.coords(&white);
+        assert!((l - 255.0).abs() < 0.01 && a.abs() < 0.01 && b.abs() < 0.01);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let colors = [
+            Colors::new(0, 0, 0, 255, 1),
+            Colors::new(255, 0, 0, 255, 1),
+            Colors::new(12, 200, 34, 255, 1),
+            Colors::new(20, 40, 250, 255, 1),
+            Colors::new(128, 128, 128, 255, 1),
+        ];
+        for space in [ColorSpace::Srgb, ColorSpace::Oklab, ColorSpace::Lab] {
+            for color in colors {
+                let [c0, c1, c2, _a] = space.coords(&color);
+                assert_eq!(space.to_srgb([c0, c1, c2]), (color.r, color.g, color.b), "{:?}", space);
+            }
+        }
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use crate::histogram::{color_key, Sampling};
+use crate::nearest::Nearest;
+use crate::options::{Dither, Options};
+use crate::palette::Palette;
+
+// 誤差を拡散する先(右方向の位置, 下方向の位置, 重み)
+type Kernel = &'static [(isize, usize, f32)];
+
+const FLOYD_STEINBERG: Kernel = &[
+    (1, 0, 7.0),
+    (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0),
+];
+
+const ATKINSON: Kernel = &[
+    (1, 0, 1.0), (2, 0, 1.0),
+    (-1, 1, 1.0), (0, 1, 1.0), (1, 1, 1.0),
+    (0, 2, 1.0),
+];
+
+const SIERRA: Kernel = &[
+    (1, 0, 5.0), (2, 0, 3.0),
+    (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 5.0), (1, 1, 4.0), (2, 1, 2.0),
+    (-1, 2, 2.0), (0, 2, 3.0), (1, 2, 2.0),
+];
+
+const STUCKI: Kernel = &[
+    (1, 0, 8.0), (2, 0, 4.0),
+    (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 8.0), (1, 1, 4.0), (2, 1, 2.0),
+    (-2, 2, 1.0), (-1, 2, 2.0), (0, 2, 4.0), (1, 2, 2.0), (2, 2, 1.0),
+];
+
+// 拡散する先と、重みを割る値
+fn kernel(dither: Dither) -> Option<(Kernel, f32)> {
+    match dither {
+        Dither::FloydSteinberg => Some((FLOYD_STEINBERG, 16.0)),
+        Dither::Atkinson => Some((ATKINSON, 8.0)),
+        Dither::Sierra => Some((SIERRA, 32.0)),
+        Dither::Stucki => Some((STUCKI, 42.0)),
+        Dither::None | Dither::Bayer | Dither::BlueNoise | Dither::Pattern => None,
+    }
+}
+
+/// 誤差拡散でディザリングしながら、各ピクセルをパレットのインデックスに置き換える
+pub(crate) fn diffuse(data: &[u8], width: usize, palette: &Palette, sampling: &Sampling, options: &Options) -> Vec<usize> {
+    let (kernel, divisor) = kernel(options.dither).expect("error diffusion kernel");
+    let height = data.len() / 4 / width;
+    let mut errors: Vec<[f32; 4]> = vec![[0.0; 4]; width * height];
+    let mut indices: Vec<usize> = vec![0; width * height];
+    let nearest = Nearest::new(palette, options.alpha);
+
+    for y in 0..height {
+        // 蛇行走査の場合、奇数行は右から左に走査し、誤差も左右反転して拡散する
+        let reverse = options.serpentine && y % 2 == 1;
+        for i in 0..width {
+            let x = if reverse { width - 1 - i } else { i };
+            let position = y * width + x;
+            let pixel = &data[position * 4..position * 4 + 4];
+            let Some((r, g, b, a)) = sampling.exact_color(pixel) else {
+                indices[position] = palette.transparent_index().unwrap();
+                continue;
+            };
+
+            let error = errors[position];
+            let color = [
+                (r as f32 + error[0]).clamp(0.0, 255.0),
+                (g as f32 + error[1]).clamp(0.0, 255.0),
+                (b as f32 + error[2]).clamp(0.0, 255.0),
+                (a as f32 + error[3]).clamp(0.0, 255.0),
+            ];
+            let index = nearest.find(color);
+            indices[position] = index;
+
+            let found = palette.colors()[index];
+            let error = [
+                color[0] - found.r as f32,
+                color[1] - found.g as f32,
+                color[2] - found.b as f32,
+                color[3] - found.a as f32,
+            ];
+            for &(dx, dy, weight) in kernel {
+                let dx = if reverse { -dx } else { dx };
+                let next_x = x as isize + dx;
+                let next_y = y + dy;
+                if next_x < 0 || next_x >= width as isize || next_y >= height {
+                    continue;
+                }
+                let factor = weight / divisor * options.dither_strength;
+                let target = &mut errors[next_y * width + next_x as usize];
+                for c in 0..4 {
+                    target[c] += error[c] * factor;
+                }
+            }
+        }
+    }
+    indices
+}
+
+/// Bayer行列による組織的ディザリングで、各ピクセルをパレットのインデックスに置き換える
+pub(crate) fn ordered(data: &[u8], width: usize, palette: &Palette, sampling: &Sampling, options: &Options) -> Vec<usize> {
+    let size = options.bayer_size as usize;
+    let matrix = bayer(size);
+    let levels = (size * size) as f32;
+    threshold(data, width, palette, sampling, options, |x, y| {
+        (matrix[y % size * size + x % size] as f32 + 0.5) / levels - 0.5
+    })
+}
+
+/// ブルーノイズのテクスチャを並べて閾値にし、各ピクセルをパレットのインデックスに置き換える
+pub(crate) fn blue_noise(data: &[u8], width: usize, palette: &Palette, sampling: &Sampling, options: &Options) -> Vec<usize> {
+    static TEXTURE: OnceLock<Vec<u32>> = OnceLock::new();
+    let texture = TEXTURE.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5));
+    let levels = (BLUE_NOISE_SIZE * BLUE_NOISE_SIZE) as f32;
+    threshold(data, width, palette, sampling, options, |x, y| {
+        (texture[y % BLUE_NOISE_SIZE * BLUE_NOISE_SIZE + x % BLUE_NOISE_SIZE] as f32 + 0.5) / levels - 0.5
+    })
+}
+
+/// Knollのパターンディザリングで、各ピクセルをパレットのインデックスに置き換える
+/// 色ごとに平均が元の色に近くなるパレットの色の組み合わせ(bayerSize²個)を選び、明るさ順に並べてBayer行列で選ぶ
+pub(crate) fn pattern(data: &[u8], width: usize, palette: &Palette, sampling: &Sampling, options: &Options) -> Vec<usize> {
+    let size = options.bayer_size as usize;
+    let matrix = bayer(size);
+    // 同じ色の組み合わせは一度だけ計算する
+    let mut mixes: HashMap<u32, Vec<usize>> = HashMap::new();
+    let nearest = Nearest::new(palette, options.alpha);
+
+    data.chunks_exact(4).enumerate().map(|(position, pixel)| {
+        let Some((r, g, b, a)) = sampling.exact_color(pixel) else {
+            return palette.transparent_index().unwrap();
+        };
+        let mix = mixes.entry(color_key(r, g, b, a)).or_insert_with(|| {
+            mix(palette, &nearest, [r as f32, g as f32, b as f32, a as f32], size * size, options)
+        });
+        let (x, y) = (position % width, position / width);
+        mix[matrix[y % size * size + x % size] as usize]
+    }).collect()
+}
+
+// 平均が元の色に近くなるパレットの色の組み合わせ(明るさ順)
+// それまでに選んだ色との誤差を足した色に最も近い色を、順に選んでいく
+fn mix(palette: &Palette, nearest: &Nearest, color: [f32; 4], count: usize, options: &Options) -> Vec<usize> {
+    let mut error = [0.0f32; 4];
+    let mut mix: Vec<usize> = Vec::with_capacity(count);
+    for _ in 0..count {
+        let attempt = [
+            (color[0] + error[0] * options.dither_strength).clamp(0.0, 255.0),
+            (color[1] + error[1] * options.dither_strength).clamp(0.0, 255.0),
+            (color[2] + error[2] * options.dither_strength).clamp(0.0, 255.0),
+            (color[3] + error[3] * options.dither_strength).clamp(0.0, 255.0),
+        ];
+        let index = nearest.find(attempt);
+        let candidate = palette.colors()[index];
+        error[0] += color[0] - candidate.r as f32;
+        error[1] += color[1] - candidate.g as f32;
+        error[2] += color[2] - candidate.b as f32;
+        error[3] += color[3] - candidate.a as f32;
+        mix.push(index);
+    }
+
+    let luminance = |index: &usize| {
+        let c = palette.colors()[*index];
+        0.299 * c.r as f32 + 0.587 * c.g as f32 + 0.114 * c.b as f32
+    };
+    mix.sort_by(|a, b| luminance(a).total_cmp(&luminance(b)));
+    mix
+}
+
+// ピクセルの位置ごとの閾値(-0.5〜0.5)にditherSpreadをかけてRGBに加え、最も近いパレットの色にする
+fn threshold(data: &[u8], width: usize, palette: &Palette, sampling: &Sampling, options: &Options, threshold: impl Fn(usize, usize) -> f32) -> Vec<usize> {
+    let nearest = Nearest::new(palette, options.alpha);
+    data.chunks_exact(4).enumerate().map(|(position, pixel)| {
+        let Some((r, g, b, a)) = sampling.exact_color(pixel) else {
+            return palette.transparent_index().unwrap();
+        };
+        let offset = threshold(position % width, position / width) * options.dither_spread;
+        let color = [
+            (r as f32 + offset).clamp(0.0, 255.0),
+            (g as f32 + offset).clamp(0.0, 255.0),
+            (b as f32 + offset).clamp(0.0, 255.0),
+            a as f32,
+        ];
+        nearest.find(color)
+    }).collect()
+}
+
+// size×sizeのBayer行列(0〜size²-1)
+// 2n×2nの行列はn×nの行列Mから [[4M, 4M+2], [4M+3, 4M+1]] で作る
+fn bayer(size: usize) -> Vec<u32> {
+    let mut matrix: Vec<u32> = vec![0];
+    let mut n = 1;
+    while n < size {
+        let mut next: Vec<u32> = vec![0; 4 * n * n];
+        for y in 0..n {
+            for x in 0..n {
+                let value = 4 * matrix[y * n + x];
+                next[y * 2 * n + x] = value;
+                next[y * 2 * n + x + n] = value + 2;
+                next[(y + n) * 2 * n + x] = value + 3;
+                next[(y + n) * 2 * n + x + n] = value + 1;
+            }
+        }
+        matrix = next;
+        n *= 2;
+    }
+    matrix
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+// void-and-cluster法でsize×sizeのブルーノイズ(0〜size²-1の順位)を作る
+// 端は反対側とつながっているものとして、タイル状に並べても継ぎ目ができないようにする
+fn void_and_cluster(size: usize, sigma: f32) -> Vec<u32> {
+    let length = size * size;
+
+    // 初期パターンとして1割の位置に1を置く(常に同じテクスチャになるよう固定のシードを使う)
+    let mut pattern = Pattern::new(size, sigma);
+    let mut seed: u32 = 0x9e3779b9;
+    let mut ones = 0;
+    while ones < length / 10 {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let position = seed as usize % length;
+        if !pattern.ones[position] {
+            pattern.set(position, true);
+            ones += 1;
+        }
+    }
+
+    // 最も密集している1を最も空いている位置に移し、動かなくなるまで繰り返す
+    loop {
+        let cluster = pattern.tightest_cluster();
+        pattern.set(cluster, false);
+        let void = pattern.largest_void();
+        pattern.set(void, true);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank: Vec<u32> = vec![0; length];
+
+    // 初期パターンから密集している1を順に取り除き、大きい順位から付ける
+    let mut removing = pattern.clone();
+    for r in (0..ones).rev() {
+        let cluster = removing.tightest_cluster();
+        removing.set(cluster, false);
+        rank[cluster] = r as u32;
+    }
+
+    // 初期パターンから最も空いている位置に順に1を置き、小さい順位から付ける
+    // 半分を超えた後の「0の中で最も密集している位置」も、1のエネルギーが最小の位置と同じになる
+    for r in ones..length {
+        let void = pattern.largest_void();
+        pattern.set(void, true);
+        rank[void] = r as u32;
+    }
+
+    rank
+}
+
+// void-and-cluster法の1と0の配置と、各位置のエネルギー(周りにある1の密度)
+// 行ごとに最も密集している1と最も空いている0を覚えておき、1つ置き換えるたびに周りの行だけを探し直す
+#[derive(Clone)]
+struct Pattern {
+    size: usize,
+    radius: usize,
+    gaussian: Vec<f32>,
+    ones: Vec<bool>,
+    energy: Vec<f32>,
+    clusters: Vec<Option<usize>>,
+    voids: Vec<Option<usize>>,
+}
+
+impl Pattern {
+    fn new(size: usize, sigma: f32) -> Pattern {
+        // 中心からの距離ごとのガウス関数の値
+        // radiusより遠くはほぼ0になるので、エネルギーはその範囲だけ更新する
+        let radius = ((4.0 * sigma).ceil() as usize).min((size - 1) / 2);
+        let side = 2 * radius + 1;
+        let mut gaussian: Vec<f32> = vec![0.0; side * side];
+        for y in 0..side {
+            for x in 0..side {
+                let dx = x as f32 - radius as f32;
+                let dy = y as f32 - radius as f32;
+                gaussian[y * side + x] = (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp();
+            }
+        }
+
+        let mut pattern = Pattern {
+            size,
+            radius,
+            gaussian,
+            ones: vec![false; size * size],
+            energy: vec![0.0; size * size],
+            clusters: vec![None; size],
+            voids: vec![None; size],
+        };
+        for y in 0..size {
+            pattern.search_row(y);
+        }
+        pattern
+    }
+
+    fn set(&mut self, position: usize, one: bool) {
+        let (size, radius) = (self.size, self.radius);
+        let side = 2 * radius + 1;
+        let sign = if one { 1.0 } else { -1.0 };
+        self.ones[position] = one;
+        // 範囲の左上から、端を超えたら反対側に戻る
+        let wrap = |value: usize| if value >= size { value - size } else { value };
+        let (left, top) = ((position % size + size - radius) % size, (position / size + size - radius) % size);
+        for dy in 0..side {
+            let y = wrap(top + dy);
+            for dx in 0..side {
+                let x = wrap(left + dx);
+                self.energy[y * size + x] += sign * self.gaussian[dy * side + dx];
+            }
+        }
+        for dy in 0..side {
+            self.search_row(wrap(top + dy));
+        }
+    }
+
+    // 同じエネルギーの場合はインデックスが小さい方にする
+    fn search_row(&mut self, y: usize) {
+        let (mut cluster, mut max) = (None, f32::MIN);
+        let (mut void, mut min) = (None, f32::MAX);
+        for i in y * self.size..(y + 1) * self.size {
+            let energy = self.energy[i];
+            if self.ones[i] {
+                if cluster.is_none() || energy > max {
+                    (cluster, max) = (Some(i), energy);
+                }
+            } else if void.is_none() || energy < min {
+                (void, min) = (Some(i), energy);
+            }
+        }
+        self.clusters[y] = cluster;
+        self.voids[y] = void;
+    }
+
+    // 1の中で最も密集している位置
+    fn tightest_cluster(&self) -> usize {
+        self.clusters.iter().flatten().copied().reduce(|best, i| if self.energy[i] > self.energy[best] { i } else { best }).unwrap()
+    }
+
+    // 0の中で最も空いている位置
+    fn largest_void(&self) -> usize {
+        self.voids.iter().flatten().copied().reduce(|best, i| if self.energy[i] < self.energy[best] { i } else { best }).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::Colors;
+
+    #[test]
+    fn test_diffuse() {
+        // 50%の灰色は黒と白が交互になる
+        let data: Vec<u8> = [128, 128, 128, 255].repeat(4);
+        let palette = Palette::new(vec![Colors::new(0, 0, 0, 255, 1), Colors::new(255, 255, 255, 255, 1)]);
+        let options = Options { dither: Dither::FloydSteinberg, ..Options::default() };
+        assert_eq!(diffuse(&data, 2, &palette, &Sampling::default(), &options), [1, 0, 0, 1]);
+
+        // 誤差を拡散しない場合は全て同じ色になる
+        let options = Options { dither_strength: 0.0, ..options };
+        assert_eq!(diffuse(&data, 2, &palette, &Sampling::default(), &options), [1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_bayer() {
+        assert_eq!(bayer(2), [0, 2, 3, 1]);
+        assert_eq!(bayer(4), [
+            0, 8, 2, 10,
+            12, 4, 14, 6,
+            3, 11, 1, 9,
+            15, 7, 13, 5,
+        ]);
+        let mut matrix = bayer(8);
+        matrix.sort();
+        assert_eq!(matrix, (0..64).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_void_and_cluster() {
+        let texture = void_and_cluster(16, 1.5);
+        let mut sorted = texture.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..256).collect::<Vec<u32>>());
+        assert_eq!(texture, void_and_cluster(16, 1.5));
+
+        // 閾値の低い1/4の点が偏らず、各4x4の範囲にほぼ同じ数だけある
+        for block in 0..16 {
+            let (bx, by) = (block % 4 * 4, block / 4 * 4);
+            let count = (0..16).filter(|i| texture[(by + i / 4) * 16 + bx + i % 4] < 64).count();
+            assert!((2..=6).contains(&count), "{count}");
+        }
+    }
+
+    #[test]
+    fn test_ordered() {
+        // 50%の灰色は市松模様になる
+        let data: Vec<u8> = [128, 128, 128, 255].repeat(16);
+        let palette = Palette::new(vec![Colors::new(0, 0, 0, 255, 1), Colors::new(255, 255, 255, 255, 1)]);
+        let options = Options { dither: Dither::Bayer, bayer_size: 2, dither_spread: 255.0, ..Options::default() };
+        assert_eq!(ordered(&data, 4, &palette, &Sampling::default(), &options), [
+            0, 1, 0, 1,
+            1, 0, 1, 0,
+            0, 1, 0, 1,
+            1, 0, 1, 0,
+        ]);
+    }
+
+    #[test]
+    fn test_blue_noise() {
+        // 50%の灰色は黒と白がほぼ半分ずつになる(128は中間の127.5よりわずかに明るい)
+        let data: Vec<u8> = [128, 128, 128, 255].repeat(64 * 64);
+        let palette = Palette::new(vec![Colors::new(0, 0, 0, 255, 1), Colors::new(255, 255, 255, 255, 1)]);
+        let options = Options { dither: Dither::BlueNoise, dither_spread: 255.0, ..Options::default() };
+        let indices = blue_noise(&data, 64, &palette, &Sampling::default(), &options);
+        assert_eq!(indices.iter().filter(|&&index| index == 1).count(), 64 * 64 / 2 + 8);
+    }
+
+    #[test]
+    fn test_mix() {
+        // 赤と青だけのパレットで紫を作る
+        let palette = Palette::new(vec![Colors::new(255, 0, 0, 255, 1), Colors::new(0, 0, 255, 255, 1)]);
+        let mix = mix(&palette, &Nearest::new(&palette, false), [128.0, 0.0, 128.0, 255.0], 4, &Options::default());
+        // 明るさ順(青→赤)に並ぶ
+        assert_eq!(mix, [1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_pattern() {
+        // 25%の灰色は4色のうち1色が白になり、Bayer行列の閾値が最大の位置に置かれる
+        let data: Vec<u8> = [64, 64, 64, 255].repeat(4);
+        let palette = Palette::new(vec![Colors::new(0, 0, 0, 255, 1), Colors::new(255, 255, 255, 255, 1)]);
+        let options = Options { dither: Dither::Pattern, bayer_size: 2, ..Options::default() };
+        assert_eq!(pattern(&data, 2, &palette, &Sampling::default(), &options), [0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_serpentine() {
+        let data: Vec<u8> = [100, 100, 100, 255].repeat(6);
+        let palette = Palette::new(vec![Colors::new(0, 0, 0, 255, 1), Colors::new(255, 255, 255, 255, 1)]);
+        let options = Options { dither: Dither::Atkinson, ..Options::default() };
+        let raster = diffuse(&data, 3, &palette, &Sampling::default(), &options);
+        let options = Options { serpentine: true, ..options };
+        let serpentine = diffuse(&data, 3, &palette, &Sampling::default(), &options);
+        // 1行目は同じで、2行目は走査の向きが変わる
+        assert_eq!(raster[0..3], serpentine[0..3]);
+        assert_ne!(raster, serpentine);
+    }
+}
@@ -0,0 +1,20 @@
+use std::fmt;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Error {
+    /// オプションの形式や値が正しくない
+    InvalidOptions(String),
+    /// 画像やパレット、インデックスなどの入力が正しくない
+    InvalidInput(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidOptions(message) => write!(f, "invalid options: {}", message),
+            Error::InvalidInput(message) => write!(f, "invalid input: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+use serde::{Serialize, Serializer};
+
+use crate::error::Error;
+use crate::options::Options;
+
+/// 色(r, g, b, a)とその色のピクセル数
+/// アルファを考慮しない場合、アルファは255になる
+#[derive(Eq, Ord, PartialEq, PartialOrd, Clone, Copy, Debug)]
+pub struct Colors {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+    pub count: u64,
+}
+
+impl Colors {
+    pub fn new(r: u8, g: u8, b: u8, a: u8, count: u64) -> Colors {
+        Colors { r, g, b, a, count }
+    }
+}
+
+// JSONでは [r, g, b, count, a] の配列にする
+impl Serialize for Colors {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.r, self.g, self.b, self.count, self.a).serialize(serializer)
+    }
+}
+
+/// ピクセルからヒストグラムの色を取り出す方法
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub(crate) struct Sampling {
+    mask: u8,
+    alpha: bool,
+    transparent: bool,
+    matte: Option<[u8; 3]>,
+}
+
+impl Sampling {
+    pub(crate) fn new(options: &Options) -> Sampling {
+        Sampling {
+            mask: 0b11111111 << (8 - options.precision),
+            alpha: options.alpha,
+            transparent: options.transparent,
+            matte: options.matte,
+        }
+    }
+
+    /// ピクセル(RGBA)をヒストグラムの色に変換する
+    /// 透明なピクセルを除外する場合、アルファが0のピクセルはNoneになる
+    pub(crate) fn color(&self, pixel: &[u8]) -> Option<(u8, u8, u8, u8)> {
+        let (r, g, b, a) = self.blended(pixel)?;
+
+        // 全ビットの場合メモリを使いすぎるので、下位ビットを無視できるようにする
+        let r = r & self.mask;
+        let g = g & self.mask;
+        let b = b & self.mask;
+        let a = if self.alpha { a & self.mask } else { 255 };
+        Some((r, g, b, a))
+    }
+
+    /// 下位ビットを無視する前の色(ディザリング向け)
+    /// アルファを考慮しない場合、アルファは255になる
+    pub(crate) fn exact_color(&self, pixel: &[u8]) -> Option<(u8, u8, u8, u8)> {
+        let (r, g, b, a) = self.blended(pixel)?;
+        Some((r, g, b, if self.alpha { a } else { 255 }))
+    }
+
+    // 透明なピクセルを除外し、マット色が指定されている場合は半透明のピクセルをマット色の上に合成する
+    fn blended(&self, pixel: &[u8]) -> Option<(u8, u8, u8, u8)> {
+        if self.transparent && pixel[3] == 0 {
+            return None;
+        }
+
+        match self.matte {
+            Some(matte) if pixel[3] < 255 => Some((
+                blend(pixel[0], matte[0], pixel[3]),
+                blend(pixel[1], matte[1], pixel[3]),
+                blend(pixel[2], matte[2], pixel[3]),
+                255,
+            )),
+            _ => Some((pixel[0], pixel[1], pixel[2], pixel[3])),
+        }
+    }
+
+    pub(crate) fn alpha(&self) -> bool {
+        self.alpha
+    }
+
+    pub(crate) fn key(&self, pixel: &[u8]) -> Option<u32> {
+        self.color(pixel).map(|(r, g, b, a)| color_key(r, g, b, a))
+    }
+
+    /// 出力するアルファ
+    /// アルファを考慮せずマット色もない場合は、元のアルファをそのまま使う
+    pub(crate) fn output_alpha(&self, pixel: &[u8], color: &Colors) -> u8 {
+        if self.alpha {
+            color.a
+        } else if self.matte.is_some() {
+            255
+        } else {
+            pixel[3]
+        }
+    }
+}
+
+fn blend(color: u8, matte: u8, alpha: u8) -> u8 {
+    let alpha = u32::from(alpha);
+    ((u32::from(color) * alpha + u32::from(matte) * (255 - alpha) + 127) / 255) as u8
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling::new(&Options::default())
+    }
+}
+
+/// 画像で使われている色とその数
+#[derive(PartialEq, Clone, Debug)]
+pub struct Histogram {
+    colors: Vec<Colors>,
+    transparent: u64,
+    sampling: Sampling,
+}
+
+impl Histogram {
+    /// RGBAのピクセル列から色数を数える
+    pub fn from_rgba(data: &[u8]) -> Histogram {
+        Histogram::with_sampling(data, Sampling::default())
+    }
+
+    /// 各チャンネルの上位precisionビット(5〜8)だけを使って色数を数える
+    pub fn with_precision(data: &[u8], precision: u8) -> Result<Histogram, Error> {
+        let options = Options { precision, ..Options::default() };
+        Histogram::with_options(data, &options)
+    }
+
+    /// オプション(precision, alpha, transparent, matte)に合わせて色数を数える
+    pub fn with_options(data: &[u8], options: &Options) -> Result<Histogram, Error> {
+        options.validate()?;
+        Ok(Histogram::with_sampling(data, Sampling::new(options)))
+    }
+
+    // Samplingは確認済みのオプションから作る
+    pub(crate) fn with_sampling(data: &[u8], sampling: Sampling) -> Histogram {
+        let (colors, transparent) = count(data, &sampling);
+        Histogram { colors, transparent, sampling }
+    }
+
+    pub fn colors(&self) -> &[Colors] {
+        &self.colors
+    }
+
+    /// 色の種類の数
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    /// ピクセル数の合計
+    pub fn total(&self) -> u64 {
+        self.colors.iter().map(|color| color.count).sum()
+    }
+
+    /// 色数から除外した透明なピクセルの数
+    pub fn transparent(&self) -> u64 {
+        self.transparent
+    }
+
+    /// 各チャンネルに適用しているビットマスク
+    pub fn mask(&self) -> u8 {
+        self.sampling.mask
+    }
+
+    /// アルファを4番目のチャンネルとして数えているか
+    pub fn alpha(&self) -> bool {
+        self.sampling.alpha
+    }
+
+    pub(crate) fn sampling(&self) -> Sampling {
+        self.sampling
+    }
+}
+
+impl From<Vec<Colors>> for Histogram {
+    fn from(colors: Vec<Colors>) -> Self {
+        Histogram { colors, transparent: 0, sampling: Sampling::default() }
+    }
+}
+
+pub(crate) fn color_key(r: u8, g: u8, b: u8, a: u8) -> u32 {
+    u32::from(r) | (u32::from(g) << 8) | (u32::from(b) << 16) | (u32::from(a) << 24)
+}
+
+pub fn calculate_count(data: &[u8]) -> Vec<Colors> {
+    count(data, &Sampling::default()).0
+}
+
+// 色ごとの数と、除外した透明なピクセルの数を返す
+fn count(data: &[u8], sampling: &Sampling) -> (Vec<Colors>, u64) {
+    let mut colors: BTreeMap<u32, Colors> = BTreeMap::new();
+    let mut transparent: u64 = 0;
+
+    for pixel in data.chunks_exact(4) {
+        let Some((r, g, b, a)) = sampling.color(pixel) else {
+            transparent += 1;
+            continue;
+        };
+        let key = color_key(r, g, b, a);
+
+        let count = match colors.get(&key) {
+            Some(val) => val.count + 1,
+            None => 1,
+        };
+
+        let new_val = Colors::new(r, g, b, a, count);
+        colors.insert(key, new_val);
+    }
+
+    (colors.into_values().collect(), transparent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calculate_count() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255];
+        let result = calculate_count(&data);
+        assert_eq!(result, [
+            Colors::new(255, 0, 0, 255, 2),
+            Colors::new(0, 255, 0, 255, 1),
+            Colors::new(0, 0, 255, 255, 1)
+        ]);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255];
+        let histogram = Histogram::from_rgba(&data);
+        assert_eq!(histogram.len(), 3);
+        assert_eq!(histogram.total(), 4);
+    }
+
+    #[test]
+    fn test_histogram_with_precision() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 250, 7, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255];
+        assert_eq!(Histogram::from_rgba(&data).len(), 4);
+
+        let histogram = Histogram::with_precision(&data, 5).unwrap();
+        assert_eq!(histogram.mask(), 0b11111000);
+        assert_eq!(histogram.colors(), [
+            Colors::new(248, 0, 0, 255, 2),
+            Colors::new(0, 248, 0, 255, 1),
+            Colors::new(0, 0, 248, 255, 1)
+        ]);
+        assert!(Histogram::with_precision(&data, 9).is_err());
+        assert!(Histogram::with_options(&data, &Options { precision: 0, ..Options::default() }).is_err());
+    }
+
+    #[test]
+    fn test_histogram_with_alpha() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 255, 0, 0, 128, 255, 0, 0, 128];
+        assert_eq!(Histogram::from_rgba(&data).colors(), [Colors::new(255, 0, 0, 255, 3)]);
+
+        let histogram = Histogram::with_options(&data, &Options { alpha: true, ..Options::default() }).unwrap();
+        assert!(histogram.alpha());
+        assert_eq!(histogram.colors(), [
+            Colors::new(255, 0, 0, 128, 2),
+            Colors::new(255, 0, 0, 255, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_histogram_with_transparent() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 0, 255, 0, 0, 255, 0, 0, 128, 0, 0, 255, 0];
+        let options = Options { transparent: true, matte: Some([255, 255, 255]), ..Options::default() };
+        let histogram = Histogram::with_options(&data, &options).unwrap();
+        assert_eq!(histogram.transparent(), 2);
+        assert_eq!(histogram.colors(), [
+            Colors::new(255, 0, 0, 255, 1),
+            Colors::new(255, 127, 127, 255, 1),
+        ]);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::histogram::Colors;
+use crate::mediancut::{samples, weighted_mean, Sample};
+use crate::nearest::Nearest;
+use crate::options::Options;
+
+/// メディアンカットのパレットを初期値にして、k-means(Lloyd法)で色を調整する
+/// 距離と平均色はcolorSpaceの色空間で求め、先頭からfixed個の色(固定する色)は動かさない
+/// 調整後のパレット(各色のピクセル数を含む)と、ヒストグラムの色ごとのパレットのインデックスを返す
+pub(crate) fn refine(colors: &[Colors], seeds: &[Colors], fixed: usize, options: &Options) -> (Vec<Colors>, Vec<usize>) {
+    let samples = samples(colors, options);
+    let mut centroids: Vec<[f32; 4]> = seeds.iter().map(|seed| options.color_space.coords(seed)).collect();
+
+    for _ in 0..options.kmeans_iterations {
+        let assignments = assign(&samples, &centroids, options.alpha);
+
+        // 1回の走査で各色に割り当てられた色をまとめる
+        let mut members: Vec<Vec<([f32; 4], u64)>> = vec![Vec::new(); centroids.len()];
+        for (sample, &index) in samples.iter().zip(&assignments) {
+            members[index].push((sample.coords, sample.color.count));
+        }
+
+        // 各色に割り当てられた色の平均を新しい色にする
+        let mut movement: f32 = 0.0;
+        for (centroid, members) in centroids.iter_mut().zip(&members).skip(fixed) {
+            // 割り当てられた色がない場合はそのままにする
+            if members.is_empty() {
+                continue;
+            }
+            let mean = weighted_mean(members.iter().copied());
+            movement = movement.max(Nearest::distance(*centroid, mean, options.alpha).sqrt());
+            *centroid = mean;
+        }
+
+        // 色の移動量が閾値未満になったら収束したとみなす
+        if movement < options.kmeans_threshold {
+            break;
+        }
+    }
+
+    let assignments = assign(&samples, &centroids, options.alpha);
+    let mut palette: Vec<Colors> = centroids.iter().map(|&[c0, c1, c2, a]| {
+        let (r, g, b) = options.color_space.to_srgb([c0, c1, c2]);
+        Colors::new(r, g, b, a.round().clamp(0.0, 255.0) as u8, 0)
+    }).collect();
+    // 固定する色は丸めの誤差が出ないように元の色のままにする
+    for (color, seed) in palette.iter_mut().zip(seeds).take(fixed) {
+        *color = Colors { count: 0, ..*seed };
+    }
+    for (color, &index) in colors.iter().zip(&assignments) {
+        palette[index].count += color.count;
+    }
+    (palette, assignments)
+}
+
+// ヒストグラムの各色を最も近い色に割り当てる
+fn assign(samples: &[Sample], centroids: &[[f32; 4]], alpha: bool) -> Vec<usize> {
+    let nearest = Nearest::from_coords(centroids, alpha);
+    samples.iter().map(|sample| nearest.find(sample.coords)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::ColorSpace;
+
+    #[test]
+    fn test_refine() {
+        let colors: Vec<Colors> = vec![
+            Colors::new(0, 0, 0, 255, 1),
+            Colors::new(20, 0, 0, 255, 1),
+            Colors::new(100, 0, 0, 255, 1),
+            Colors::new(120, 0, 0, 255, 1),
+        ];
+        // 偏った初期値から、2つの塊の中心に移動する
+        let seeds: Vec<Colors> = vec![Colors::new(0, 0, 0, 255, 0), Colors::new(20, 0, 0, 255, 0)];
+        let options = Options { kmeans_iterations: 10, ..Options::default() };
+        let (palette, assignments) = refine(&colors, &seeds, 0, &options);
+        assert_eq!(palette, [Colors::new(10, 0, 0, 255, 2), Colors::new(110, 0, 0, 255, 2)]);
+        assert_eq!(assignments, [0, 0, 1, 1]);
+
+        // 回数が0の場合は初期値のまま割り当てる
+        let (palette, assignments) = refine(&colors, &seeds, 0, &Options::default());
+        assert_eq!(palette, [Colors::new(0, 0, 0, 255, 1), Colors::new(20, 0, 0, 255, 3)]);
+        assert_eq!(assignments, [0, 1, 1, 1]);
+
+        // 固定する色は動かさない
+        let seeds: Vec<Colors> = vec![Colors::new(0, 0, 0, 255, 3), Colors::new(20, 0, 0, 255, 1)];
+        let (palette, _) = refine(&colors, &seeds, 1, &options);
+        assert_eq!(palette[0], Colors::new(0, 0, 0, 255, 2));
+    }
+
+    #[test]
+    fn test_refine_in_color_space() {
+        // 暗い灰色(45)はsRGBでは灰色(120)より黒に近いが、OKLabでは灰色の方が近い
+        let colors: Vec<Colors> = vec![
+            Colors::new(0, 0, 0, 255, 1),
+            Colors::new(45, 45, 45, 255, 1),
+            Colors::new(120, 120, 120, 255, 1),
+        ];
+        let seeds: Vec<Colors> = vec![Colors::new(0, 0, 0, 255, 0), Colors::new(120, 120, 120, 255, 0)];
+        let (_, assignments) = refine(&colors, &seeds, 2, &Options::default());
+        assert_eq!(assignments, [0, 0, 1]);
+        let options = Options { color_space: ColorSpace::Oklab, ..Options::default() };
+        let (palette, assignments) = refine(&colors, &seeds, 2, &options);
+        assert_eq!(assignments, [0, 1, 1]);
+        assert_eq!(palette[1], Colors::new(120, 120, 120, 255, 2));
+    }
+}
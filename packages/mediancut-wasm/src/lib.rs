@@ -1,9 +1,27 @@
-use std::collections::HashMap;
-use std::collections::BTreeMap;
-use std::cmp::min;
-use std::cmp::max;
+mod color;
+mod dither;
+mod error;
+mod histogram;
+mod kmeans;
+mod mediancut;
+mod nearest;
+mod octree;
+mod options;
+mod palette;
+mod presets;
+mod quantizer;
+mod wu;
+
 use wasm_bindgen::{prelude::*};
 
+pub use error::Error;
+pub use histogram::{calculate_count, Colors, Histogram};
+pub use mediancut::{average_color, BucketSnapshot};
+pub use options::{Algorithm, BucketSelection, ColorSpace, CutPosition, Dither, Options, SplitAxis};
+pub use palette::Palette;
+pub use presets::{FixedPalette, Preset};
+pub use quantizer::{Quantization, Quantizer};
+
 #[wasm_bindgen]
 extern "C" {
     pub fn alert(s: &str);
@@ -11,220 +29,166 @@ extern "C" {
     fn log(s: &str);
 }
 
-
-#[derive(Eq, Ord, PartialEq, PartialOrd, Debug)]
-enum Channel {
-    R = 0,
-    G,
-    B,
+#[wasm_bindgen]
+pub fn reduce(data: &[u8], size: u8) -> Vec<u8> {
+    Quantizer::new(size).quantize(&Histogram::from_rgba(data)).reduce(data)
 }
 
-#[derive(Eq, Ord, PartialEq, PartialOrd, Clone, Copy, Debug)]
-struct Colors(u8, u8, u8, u64);
-
-#[derive(Eq, Ord, PartialEq, PartialOrd, Debug)]
-struct Bucket {
-    colors: Vec<Colors>,
-    total: u64,
-    channel: Channel,
-    min_r: u8,
-    min_g: u8,
-    min_b: u8,
-    max_r: u8,
-    max_g: u8,
-    max_b: u8,
+// 各チャンネルの上位precisionビット(5〜8)だけを使って減色する
+// TypeScript版の`strict: false`はprecisionが5の場合と同じ
+#[wasm_bindgen]
+pub fn reduce_with_precision(data: &[u8], size: u8, precision: u8) -> Result<Vec<u8>, JsError> {
+    let quantizer = Quantizer::new(size).with_precision(precision)?;
+    Ok(quantizer.quantize(&quantizer.histogram(data)).reduce(data))
 }
 
-fn calculate_count(data: &[u8]) -> Vec<Colors> {
-    let mut colors: BTreeMap<u32, Colors> = BTreeMap::new();
-    let length = data.len();
-    let mut i: usize = 0;
-
-    while i < length {
-        let r = data[i];
-        let g = data[i + 1];
-        let b = data[i + 2];
-        let key: u32 = u32::from(r) | (u32::from(g) << 8) | (u32::from(b) << 16);
-
-        let count = match colors.get(&key) {
-            Some(val) => val.3 + 1,
-            None => 1,
-        };
+// パレット(平均色とその色で置き換えられるピクセル数)をJSONの配列 [[r, g, b, count, a], ...] で返す
+#[wasm_bindgen]
+pub fn palette(data: &[u8], size: u8) -> String {
+    let quantization = Quantizer::new(size).quantize(&Histogram::from_rgba(data));
+    serde_json::to_string(quantization.palette()).unwrap()
+}
 
-        let new_val = Colors(r, g, b, count);
-        colors.insert(key, new_val);
+// パレット([r, g, b, r, g, b, ...])とパレットの各色のアルファ、ピクセルごとのパレットのインデックス
+#[wasm_bindgen]
+pub struct IndexedImage {
+    palette: Vec<u8>,
+    alpha: Vec<u8>,
+    indices: Vec<u8>,
+    transparent_index: Option<u8>,
+}
 
-        i = i + 4;
+impl IndexedImage {
+    fn new(palette: Palette, indices: Vec<u8>) -> IndexedImage {
+        let transparent_index = palette.transparent_index().map(|index| index as u8);
+        IndexedImage { palette: palette.to_rgb(), alpha: palette.alpha(), indices, transparent_index }
     }
+}
 
-    let mut result: Vec<Colors> = Vec::new();
+#[wasm_bindgen]
+impl IndexedImage {
+    #[wasm_bindgen(getter)]
+    pub fn palette(&self) -> Vec<u8> {
+        self.palette.clone()
+    }
 
-    for (_k, val) in colors {
-        result.push(Colors(val.0, val.1, val.2, val.3));
+    // PNGのtRNS向け(alphaオプションが無効な場合は全て255)
+    #[wasm_bindgen(getter)]
+    pub fn alpha(&self) -> Vec<u8> {
+        self.alpha.clone()
     }
-    result
-}
 
-fn average_color(colors: Vec<Colors>) -> (u8, u8, u8) {
-    let mut count = 0.0;
-    let mut r = 0.0;
-    let mut g = 0.0;
-    let mut b = 0.0;
-
-    for (_i, color) in colors.iter().enumerate() {
-        let c = color.clone();
-        let _r = c.0 as f32;
-        let _g = c.1 as f32;
-        let _b = c.2 as f32;
-        let _count = c.3 as f32;
-
-        r = (_r * _count) + r;
-        g = (_g * _count) + g;
-        b = (_b * _count) + b;
-        count = count + _count;
+    #[wasm_bindgen(getter)]
+    pub fn indices(&self) -> Vec<u8> {
+        self.indices.clone()
     }
 
-    let result_r = ((r / count) as f32).round();
-    let result_g = ((g / count) as f32).round();
-    let result_b = ((b / count) as f32).round();
+    // transparentオプションで透明用に確保したインデックス(オプションが無効な場合はundefined)
+    #[wasm_bindgen(getter)]
+    pub fn transparent_index(&self) -> Option<u8> {
+        self.transparent_index
+    }
+}
 
-    (result_r as u8, result_g as u8, result_b as u8)
+// RGBAではなくパレットとインデックスで減色結果を返す(GIFやPNG-8向け)
+#[wasm_bindgen]
+pub fn reduce_indexed(data: &[u8], size: u8) -> IndexedImage {
+    let quantization = Quantizer::new(size).quantize(&Histogram::from_rgba(data));
+    let indices = quantization.indices(data);
+    IndexedImage::new(quantization.palette().clone(), indices)
 }
 
-fn get_total_and_greatest_range_channel(colors: Vec<Colors>) -> Bucket {
-    let mut total: u64 = 0;
-    let mut max_r = 0;
-    let mut max_g = 0;
-    let mut max_b = 0;
-    let mut min_r = 255;
-    let mut min_g = 255;
-    let mut min_b = 255;
-    let length = colors.len();
-    let mut i: usize = 0;
-
-    while i < length {
-        let r = colors[i].0;
-        let g = colors[i].1;
-        let b = colors[i].2;
-        max_r = max(r, max_r);
-        max_g = max(g, max_g);
-        max_b = max(b, max_b);
-        min_r = min(r, min_r);
-        min_g = min(g, min_g);
-        min_b = min(b, min_b);
-        total = total + colors[i].3;
-        i = i + 1;
+// パレットとインデックスからRGBAを復元する
+// alphaはパレットの色ごとの透明度(IndexedImageのalpha)で、空の場合は不透明(255)とする
+#[wasm_bindgen]
+pub fn expand(palette: &[u8], indices: &[u8], alpha: &[u8]) -> Result<Vec<u8>, JsError> {
+    let mut palette = Palette::from_rgb(palette)?;
+    if !alpha.is_empty() {
+        palette = palette.with_alpha(alpha)?;
     }
+    Ok(palette.expand(indices, &[])?)
+}
 
+// 別の画像などで作ったパレット([r, g, b, r, g, b, ...])の最も近い色に置き換えたRGBAを返す
+// アルファはそのまま、パレットの長さが3の倍数でない場合や1〜256色でない場合は例外を投げる
+#[wasm_bindgen]
+pub fn remap(data: &[u8], palette: &[u8]) -> Result<Vec<u8>, JsError> {
+    Ok(Quantization::from_palette(Palette::from_rgb(palette)?, &Options::default())?.reduce(data))
+}
 
-    // 目は赤と緑が認識しやすいのでRとGに係数をかける
-    let diff_r = (max_r - min_r) as f32 * 1.2;
-    let diff_g = (max_g - min_g) as f32 * 1.2;
-    let diff_b = (max_b - min_b) as f32;
-
-    // 同一の場合はrを優先する
-    let mut channel = Channel::R;
-    let mut new_colors = colors;
-
-    if diff_r >= diff_g && diff_r >= diff_b {
-        channel = Channel::R;
-        new_colors.sort_by(|a, b| a.0.cmp(&b.0));
-    }
-    if diff_g >= diff_r && diff_g >= diff_b {
-        channel = Channel::G;
-        new_colors.sort_by(|a, b| a.1.cmp(&b.1));
-    }
-    if diff_b >= diff_r && diff_b >= diff_g {
-        channel = Channel::B;
-        new_colors.sort_by(|a, b| a.2.cmp(&b.2));
-    }
-
-    return Bucket { colors: new_colors, total: total, channel, min_r, min_g, min_b, max_r, max_g, max_b };
+// 分割の過程を1ステップごとにJSONの配列で返す(デモでの分割のアニメーション用)
+#[wasm_bindgen]
+pub fn steps(data: &[u8], size: u8) -> String {
+    let steps = Quantizer::new(size).steps(&Histogram::from_rgba(data));
+    serde_json::to_string(&steps).unwrap()
 }
 
+// 減色したRGBAと分割の過程(JSON)
 #[wasm_bindgen]
-pub fn reduce(data: &[u8], size: u8) -> Vec<u8> {
-    if data.len() < 1 {
-        ()
-    }
+pub struct ReducedImage {
+    data: Vec<u8>,
+    steps: String,
+}
 
-    let count_by_color = calculate_count(data);
-
-    // 再帰的に分割をしていく（lengthがcolorSizeになるまで）
-    let buckets = fact(vec![get_total_and_greatest_range_channel(count_by_color)], size as usize);
-
-    // 平均色を求める
-    let mut palette_map: HashMap<u32, (u8, u8, u8)> = HashMap::new();
-    for (_i, bucket) in buckets.iter().enumerate() {
-        let colors = bucket.colors.clone();
-        let palette = average_color(colors);
-        for (_j, color) in bucket.colors.iter().enumerate() {
-            let r = color.0;
-            let g = color.1;
-            let b = color.2;
-            let key: u32 = u32::from(r) | (u32::from(g) << 8) | (u32::from(b) << 16);
-            palette_map.insert(key, palette);
-        }
+#[wasm_bindgen]
+impl ReducedImage {
+    #[wasm_bindgen(getter)]
+    pub fn data(&self) -> Vec<u8> {
+        self.data.clone()
     }
 
-    // 平均色を元に色を置き換えていく
-    let mut i: usize = 0;
-    let mut image_data: Vec<u8> = vec![];
-    while i < data.len() {
-        let r = data[i];
-        let g = data[i + 1];
-        let b = data[i + 2];
-        let a = data[i + 3];
-        let key: u32 = u32::from(r) | (u32::from(g) << 8) | (u32::from(b) << 16);
-        let color = palette_map.get(&key).unwrap();
-        image_data.push(color.0);
-        image_data.push(color.1);
-        image_data.push(color.2);
-        image_data.push(a);
-        i = i + 4;
+    #[wasm_bindgen(getter)]
+    pub fn steps(&self) -> String {
+        self.steps.clone()
     }
-    image_data
 }
 
-fn fact(buckets: Vec<Bucket>, size: usize) -> Vec<Bucket> {
-
-    // TODO: 分割過程でのbucketsを保持しておく
+// reduceとstepsの結果を1回の減色でまとめて返す(デモ向け)
+#[wasm_bindgen]
+pub fn reduce_with_steps(data: &[u8], size: u8) -> ReducedImage {
+    let (quantization, steps) = Quantizer::new(size).quantize_and_steps(&Histogram::from_rgba(data));
+    ReducedImage { data: quantization.reduce(data), steps: serde_json::to_string(&steps).unwrap() }
+}
 
-    let mut count = 0;
-    let mut largest_bucket_index: usize = 0;
+// オプション(JSON)を指定して減色する
+// 例: new Quantizer(JSON.stringify({ size: 12, precision: 5 }))
+#[wasm_bindgen(js_name = Quantizer)]
+pub struct JsQuantizer {
+    quantizer: Quantizer,
+}
 
-    if buckets.len() + 1 > size {
-        return buckets;
+#[wasm_bindgen(js_class = Quantizer)]
+impl JsQuantizer {
+    #[wasm_bindgen(constructor)]
+    pub fn new(options: &str) -> Result<JsQuantizer, JsError> {
+        let quantizer = Quantizer::from_options(Options::from_json(options)?)?;
+        Ok(JsQuantizer { quantizer })
     }
 
-    // 面積(色数)が最大のbucketを選択
-    for (i, bucket) in buckets.iter().enumerate() {
-        if bucket.total > count && bucket.colors.len() != 1 {
-            largest_bucket_index = i;
-            count = bucket.total;
-        }
+    // widthが0の場合や、ピクセル数を割り切れない場合は例外を投げる
+    pub fn reduce(&self, data: &[u8], width: usize) -> Result<Vec<u8>, JsError> {
+        Ok(self.quantizer.reduce(data, width)?)
     }
 
-    let target_bucket = buckets.get(largest_bucket_index).unwrap();
-
-    if target_bucket.total == 1 || target_bucket.colors.len() == 1 {
-        return buckets;
+    pub fn reduce_indexed(&self, data: &[u8], width: usize) -> Result<IndexedImage, JsError> {
+        let (palette, indices) = self.quantizer.reduce_indexed(data, width)?;
+        Ok(IndexedImage::new(palette, indices))
     }
 
-    // bucketを分割
-    let median = (((target_bucket.colors.len() + 1) / 2) as f32).floor() as usize;
-
-    let split_colors1 = (&target_bucket.colors[0..median]).to_vec();
-    let split_colors2 = (&target_bucket.colors[median..target_bucket.colors.len()]).to_vec();
-    let split_bucket1 = get_total_and_greatest_range_channel(split_colors1);
-    let split_bucket2 = get_total_and_greatest_range_channel(split_colors2);
+    // パレットは[r, g, b, r, g, b, ...]
+    pub fn remap(&self, data: &[u8], width: usize, palette: &[u8]) -> Result<Vec<u8>, JsError> {
+        Ok(self.quantizer.remap(data, width, &Palette::from_rgb(palette)?)?)
+    }
 
-    // bucketを分割
-    let new_vec = vec![split_bucket1, split_bucket2];
-    let mut new_buckets = buckets;
-    new_buckets.splice(largest_bucket_index..largest_bucket_index + 1, new_vec);
+    pub fn palette(&self, data: &[u8]) -> String {
+        let quantization = self.quantizer.quantize(&self.quantizer.histogram(data));
+        serde_json::to_string(quantization.palette()).unwrap()
+    }
 
-    fact(new_buckets, size)
+    pub fn steps(&self, data: &[u8]) -> String {
+        let steps = self.quantizer.steps(&self.quantizer.histogram(data));
+        serde_json::to_string(&steps).unwrap()
+    }
 }
 
 #[cfg(test)]
@@ -232,25 +196,71 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_calculate_count() {
+    fn test_palette() {
         let data: Vec<u8> = vec![255, 0, 0, 255, 255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255];
-        let result = calculate_count(&data);
-        assert_eq!(result, [
-            Colors(255, 0, 0, 2),
-            Colors(0, 255, 0, 1),
-            Colors(0, 0, 255, 1)
+        assert_eq!(palette(&data, 2), "[[170,0,85,3,255],[0,255,0,1,255]]");
+    }
+
+    #[test]
+    fn test_reduce_indexed() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 255, 0, 0, 128, 0, 255, 0, 255, 0, 0, 255, 255];
+        let result = reduce_indexed(&data, 2);
+        assert_eq!(result.palette(), [170, 0, 85, 0, 255, 0]);
+        assert_eq!(result.alpha(), [255, 255]);
+        assert_eq!(result.indices(), [0, 0, 1, 0]);
+
+        assert_eq!(expand(&result.palette(), &result.indices(), &result.alpha()).ok().unwrap(), [
+            170, 0, 85, 255, 170, 0, 85, 255, 0, 255, 0, 255, 170, 0, 85, 255,
         ]);
+
+        // パレットのアルファで、透明用の色や量子化したアルファも復元できる
+        let data: Vec<u8> = vec![255, 0, 0, 255, 255, 0, 0, 128, 0, 0, 0, 0, 0, 0, 255, 255];
+        let quantizer = JsQuantizer::new(r#"{"size": 4, "alpha": true, "transparent": true}"#).ok().unwrap();
+        let result = quantizer.reduce_indexed(&data, 4).ok().unwrap();
+        assert_eq!(result.alpha(), [255, 128, 255, 0]);
+        assert_eq!(expand(&result.palette(), &result.indices(), &result.alpha()).ok().unwrap(), quantizer.reduce(&data, 4).ok().unwrap());
+    }
+
+    #[test]
+    fn test_quantizer_with_options() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 250, 7, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255];
+        let quantizer = JsQuantizer::new(r#"{"size": 4, "precision": 5}"#).ok().unwrap();
+        assert_eq!(quantizer.reduce(&data, 2).ok().unwrap(), reduce_with_precision(&data, 4, 5).ok().unwrap());
+        assert_eq!(JsQuantizer::new("{}").ok().unwrap().reduce(&data, 2).ok().unwrap(), reduce(&data, 16));
+    }
+
+    #[test]
+    fn test_quantizer_with_dither() {
+        // 4x2の灰色のグラデーションを2色にする
+        let data: Vec<u8> = (0..8).flat_map(|i| [i * 36, i * 36, i * 36, 255]).collect();
+        assert_eq!(reduce_indexed(&data, 2).indices(), [0, 0, 0, 0, 1, 1, 1, 1]);
+
+        // 1行目の誤差が2行目に拡散される
+        let quantizer = JsQuantizer::new(r#"{"size": 2, "dither": "floydSteinberg"}"#).ok().unwrap();
+        let result = quantizer.reduce_indexed(&data, 4).ok().unwrap();
+        assert_eq!(result.indices(), [0, 0, 0, 0, 0, 1, 1, 1]);
+        assert_eq!(expand(&result.palette(), &result.indices(), &[]).ok().unwrap(), quantizer.reduce(&data, 4).ok().unwrap());
     }
 
     #[test]
-    fn test_average_color() {
-        let colors: Vec<Colors> = vec![
-            Colors(255, 0, 0, 2),
-            Colors(0, 255, 0, 1),
-            Colors(0, 0, 255, 1),
-        ];
-        let result = average_color(colors.clone());
-        println!("{:?}", result);
-        assert_eq!(result, (128, 64, 64));
+    fn test_remap() {
+        // 1枚目のパレットを、1枚目にない色を含む2枚目に使う
+        let first: Vec<u8> = vec![255, 0, 0, 255, 0, 0, 255, 255];
+        let second: Vec<u8> = vec![200, 10, 10, 255, 10, 10, 200, 128];
+        let result = reduce_indexed(&first, 2);
+        assert_eq!(remap(&second, &result.palette()).ok().unwrap(), [255, 0, 0, 255, 0, 0, 255, 128]);
+
+        let quantizer = JsQuantizer::new(r#"{"dither": "bayer"}"#).ok().unwrap();
+        assert_eq!(quantizer.remap(&second, 2, &result.palette()).ok().unwrap().len(), second.len());
+    }
+
+    #[test]
+    fn test_steps() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255];
+        assert!(steps(&data, 2).starts_with("[[{\"total\":4,\"channel\":1,\"minR\":0,"));
+
+        let result = reduce_with_steps(&data, 2);
+        assert_eq!(result.data(), reduce(&data, 2));
+        assert_eq!(result.steps(), steps(&data, 2));
     }
 }
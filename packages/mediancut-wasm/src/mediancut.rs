@@ -0,0 +1,607 @@
+use std::cmp::min;
+use std::cmp::max;
+use serde::Serialize;
+use crate::color::{linear_to_srgb, srgb_to_linear};
+use crate::histogram::Colors;
+use crate::options::{BucketSelection, CutPosition, Options, SplitAxis};
+
+#[derive(Eq, Ord, PartialEq, PartialOrd, Clone, Copy, Debug)]
+pub(crate) enum Channel {
+    R = 0,
+    G,
+    B,
+    A,
+}
+
+/// 分割に使う色(ヒストグラムの色と、分割に使う色空間での座標)
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub(crate) struct Sample {
+    pub(crate) color: Colors,
+    pub(crate) coords: [f32; 4],
+}
+
+pub(crate) fn samples(colors: &[Colors], options: &Options) -> Vec<Sample> {
+    colors.iter().map(|color| Sample { color: *color, coords: options.color_space.coords(color) }).collect()
+}
+
+/// bucketに入れる色
+/// sRGBではヒストグラムの色をそのまま使い、他の色空間では座標を求めておいたSampleを使う
+pub(crate) trait Point: Copy {
+    fn color(&self) -> &Colors;
+    /// 分割に使う色空間での座標 [c0, c1, c2, a]
+    fn coords(&self) -> [f32; 4];
+    /// channelの座標の順に並べる
+    fn sort(points: &mut [Self], channel: Channel);
+    /// ピクセル数で重み付けした平均色(sRGB)
+    fn average(points: &[Self], options: &Options) -> (u8, u8, u8, u8);
+}
+
+impl Point for Colors {
+    fn color(&self) -> &Colors {
+        self
+    }
+
+    fn coords(&self) -> [f32; 4] {
+        [self.r as f32, self.g as f32, self.b as f32, self.a as f32]
+    }
+
+    fn sort(points: &mut [Self], channel: Channel) {
+        match channel {
+            Channel::R => points.sort_by_key(|c| c.r),
+            Channel::G => points.sort_by_key(|c| c.g),
+            Channel::B => points.sort_by_key(|c| c.b),
+            Channel::A => points.sort_by_key(|c| c.a),
+        }
+    }
+
+    fn average(points: &[Self], options: &Options) -> (u8, u8, u8, u8) {
+        if options.linear_average { average_linear(points) } else { average(points) }
+    }
+}
+
+impl Point for Sample {
+    fn color(&self) -> &Colors {
+        &self.color
+    }
+
+    fn coords(&self) -> [f32; 4] {
+        self.coords
+    }
+
+    fn sort(points: &mut [Self], channel: Channel) {
+        let i = channel as usize;
+        points.sort_by(|a, b| a.coords[i].total_cmp(&b.coords[i]));
+    }
+
+    fn average(points: &[Self], options: &Options) -> (u8, u8, u8, u8) {
+        average_samples(points, options)
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub(crate) struct Bucket<P: Point> {
+    pub(crate) colors: Vec<P>,
+    pub(crate) total: u64,
+    pub(crate) channel: Channel,
+    pub(crate) min_r: u8,
+    pub(crate) min_g: u8,
+    pub(crate) min_b: u8,
+    pub(crate) max_r: u8,
+    pub(crate) max_g: u8,
+    pub(crate) max_b: u8,
+    pub(crate) min_a: u8,
+    pub(crate) max_a: u8,
+    // 分割に使う色空間での最小値と最大値
+    pub(crate) low: [f32; 4],
+    pub(crate) high: [f32; 4],
+    pub(crate) average: (u8, u8, u8, u8),
+    // 平均色との二乗誤差の合計(チャンネルごとの係数の二乗で重み付け)
+    pub(crate) error: f64,
+}
+
+/// 分割過程のbucket(色の一覧は含めない)
+/// JSONにした時にTypeScript版のCuboidと同じキーになるようにする
+#[derive(PartialEq, Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BucketSnapshot {
+    pub total: u64,
+    pub channel: u8,
+    pub min_r: u8,
+    pub min_g: u8,
+    pub min_b: u8,
+    pub max_r: u8,
+    pub max_g: u8,
+    pub max_b: u8,
+    pub min_a: u8,
+    pub max_a: u8,
+    pub average: (u8, u8, u8, u8),
+}
+
+impl<P: Point> From<&Bucket<P>> for BucketSnapshot {
+    fn from(bucket: &Bucket<P>) -> Self {
+        BucketSnapshot {
+            total: bucket.total,
+            channel: bucket.channel as u8,
+            min_r: bucket.min_r,
+            min_g: bucket.min_g,
+            min_b: bucket.min_b,
+            max_r: bucket.max_r,
+            max_g: bucket.max_g,
+            max_b: bucket.max_b,
+            min_a: bucket.min_a,
+            max_a: bucket.max_a,
+            average: bucket.average,
+        }
+    }
+}
+
+/// ピクセル数で重み付けした平均色
+pub fn average_color(colors: Vec<Colors>) -> (u8, u8, u8) {
+    let (r, g, b, _a) = average(&colors);
+    (r, g, b)
+}
+
+pub(crate) fn average(colors: &[Colors]) -> (u8, u8, u8, u8) {
+    let [r, g, b, a] = weighted_mean(colors.iter().map(|c| ([c.r as f32, c.g as f32, c.b as f32, c.a as f32], c.count)));
+    (r.round() as u8, g.round() as u8, b.round() as u8, a.round() as u8)
+}
+
+// sRGBをリニアに戻してから平均を求める(赤と緑の境界などが暗くならない)
+pub(crate) fn average_linear(colors: &[Colors]) -> (u8, u8, u8, u8) {
+    let [r, g, b, a] = weighted_mean(colors.iter().map(|c| ([srgb_to_linear(c.r), srgb_to_linear(c.g), srgb_to_linear(c.b), c.a as f32], c.count)));
+    (linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b), a.round() as u8)
+}
+
+// 分割に使う色空間で平均を求めてsRGBに戻す
+pub(crate) fn average_samples(samples: &[Sample], options: &Options) -> (u8, u8, u8, u8) {
+    let [c0, c1, c2, a] = weighted_mean(samples.iter().map(|s| (s.coords, s.color.count)));
+    let (r, g, b) = options.color_space.to_srgb([c0, c1, c2]);
+    (r, g, b, a.round() as u8)
+}
+
+// ピクセル数で重み付けした平均 [c0, c1, c2, a]
+// c0〜c2はアルファでも重み付けする(透明に近い色ほど平均色への影響を小さくする)
+pub(crate) fn weighted_mean(values: impl Iterator<Item = ([f32; 4], u64)> + Clone) -> [f32; 4] {
+    let mut count = 0.0;
+    let mut weight = 0.0;
+    let mut r = 0.0;
+    let mut g = 0.0;
+    let mut b = 0.0;
+    let mut a = 0.0;
+
+    for ([_r, _g, _b, _a], _count) in values.clone() {
+        let _count = _count as f32;
+        let _weight = _count * (_a / 255.0);
+
+        r += _r * _weight;
+        g += _g * _weight;
+        b += _b * _weight;
+        a += _a * _count;
+        weight += _weight;
+        count += _count;
+    }
+
+    // 全て透明な場合はピクセル数だけで重み付けする
+    if weight == 0.0 {
+        r = 0.0;
+        g = 0.0;
+        b = 0.0;
+        for ([_r, _g, _b, _a], _count) in values {
+            r += _r * _count as f32;
+            g += _g * _count as f32;
+            b += _b * _count as f32;
+        }
+        weight = count;
+    }
+
+    [r / weight, g / weight, b / weight, a / count]
+}
+
+pub(crate) fn get_total_and_greatest_range_channel<P: Point>(colors: Vec<P>, options: &Options) -> Bucket<P> {
+    let mut total: u64 = 0;
+    let mut max_r = 0;
+    let mut max_g = 0;
+    let mut max_b = 0;
+    let mut min_r = 255;
+    let mut min_g = 255;
+    let mut min_b = 255;
+    let mut max_a = 0;
+    let mut min_a = 255;
+    let mut low = [f32::MAX; 4];
+    let mut high = [f32::MIN; 4];
+    // 分散と二乗誤差を求めるためのチャンネルごとの合計と二乗の合計
+    let mut sum = [0.0f64; 4];
+    let mut square_sum = [0.0f64; 4];
+
+    for sample in &colors {
+        let color = sample.color();
+        let r = color.r;
+        let g = color.g;
+        let b = color.b;
+        max_r = max(r, max_r);
+        max_g = max(g, max_g);
+        max_b = max(b, max_b);
+        min_r = min(r, min_r);
+        min_g = min(g, min_g);
+        min_b = min(b, min_b);
+        max_a = max(color.a, max_a);
+        min_a = min(color.a, min_a);
+        total += color.count;
+        let count = color.count as f64;
+        for (i, value) in sample.coords().into_iter().enumerate() {
+            low[i] = low[i].min(value);
+            high[i] = high[i].max(value);
+            sum[i] += value as f64 * count;
+            square_sum[i] += value as f64 * value as f64 * count;
+        }
+    }
+
+
+    // チャンネルごとの係数をかける(デフォルトは目が認識しやすいRとGを大きくしている)
+    // OKLabやCIELABの場合はRGBではなくその色空間の各チャンネルにかける
+    let [weight_r, weight_g, weight_b] = options.weights();
+    let (diff_r, diff_g, diff_b, diff_a) = match options.split_axis {
+        SplitAxis::Range => (
+            (high[0] - low[0]) * weight_r,
+            (high[1] - low[1]) * weight_g,
+            (high[2] - low[2]) * weight_b,
+            (high[3] - low[3]) * options.alpha_weight,
+        ),
+        // 分散は値の二乗の単位なので、係数も二乗してかける
+        SplitAxis::Variance => {
+            let variance = |i: usize| {
+                let mean = sum[i] / total as f64;
+                (square_sum[i] / total as f64 - mean * mean).max(0.0) as f32
+            };
+            (
+                variance(0) * weight_r * weight_r,
+                variance(1) * weight_g * weight_g,
+                variance(2) * weight_b * weight_b,
+                variance(3) * options.alpha_weight * options.alpha_weight,
+            )
+        }
+    };
+
+    // 同一の場合はrを優先する
+    let mut channel = Channel::R;
+    let mut new_colors = colors;
+
+    let sort_by = |colors: &mut Vec<P>, channel: Channel| P::sort(colors, channel);
+    if diff_r >= diff_g && diff_r >= diff_b {
+        channel = Channel::R;
+        sort_by(&mut new_colors, channel);
+    }
+    if diff_g >= diff_r && diff_g >= diff_b {
+        channel = Channel::G;
+        sort_by(&mut new_colors, channel);
+    }
+    if diff_b >= diff_r && diff_b >= diff_g {
+        channel = Channel::B;
+        sort_by(&mut new_colors, channel);
+    }
+    // アルファを考慮しない場合は常に255なので選ばれない
+    if diff_a > diff_r && diff_a > diff_g && diff_a > diff_b {
+        channel = Channel::A;
+        sort_by(&mut new_colors, channel);
+    }
+
+    let average = P::average(&new_colors, options);
+
+    let mut error = 0.0;
+    if total > 0 {
+        let channel_weights = [weight_r, weight_g, weight_b, options.alpha_weight];
+        for i in 0..4 {
+            let weight = channel_weights[i] as f64;
+            error += (square_sum[i] - sum[i] * sum[i] / total as f64).max(0.0) * weight * weight;
+        }
+    }
+
+    Bucket { colors: new_colors, total, channel, min_r, min_g, min_b, max_r, max_g, max_b, min_a, max_a, low, high, average, error }
+}
+
+pub(crate) fn fact<P: Point>(buckets: Vec<Bucket<P>>, options: &Options, steps: &mut Vec<Vec<BucketSnapshot>>) -> Vec<Bucket<P>> {
+
+    // 分割過程でのbucketsを保持しておく
+    steps.push(buckets.iter().map(BucketSnapshot::from).collect());
+
+    let mut largest = 0.0;
+    let mut largest_bucket_index: usize = 0;
+
+    if buckets.len() + 1 > options.size as usize {
+        return buckets;
+    }
+
+    // 分割するbucketを選択(デフォルトは面積(色数)が最大のbucket)
+    for (i, bucket) in buckets.iter().enumerate() {
+        let score = selection_score(bucket, options);
+        if score > largest && bucket.colors.len() != 1 {
+            largest_bucket_index = i;
+            largest = score;
+        }
+    }
+
+    let target_bucket = buckets.get(largest_bucket_index).unwrap();
+
+    // 空のbucket(空の画像や、全て透明なピクセルの場合)や1色だけのbucketは分割できない
+    if target_bucket.total <= 1 || target_bucket.colors.len() < 2 {
+        return buckets;
+    }
+
+    // bucketを分割
+    let median = cut_index(target_bucket, options);
+
+    let split_colors1 = target_bucket.colors[0..median].to_vec();
+    let split_colors2 = target_bucket.colors[median..target_bucket.colors.len()].to_vec();
+    let split_bucket1 = get_total_and_greatest_range_channel(split_colors1, options);
+    let split_bucket2 = get_total_and_greatest_range_channel(split_colors2, options);
+
+    // bucketを分割
+    let new_vec = vec![split_bucket1, split_bucket2];
+    let mut new_buckets = buckets;
+    new_buckets.splice(largest_bucket_index..largest_bucket_index + 1, new_vec);
+
+    fact(new_buckets, options, steps)
+}
+
+// 分割するbucketを選ぶ時の評価値(大きいものから分割する)
+fn selection_score<P: Point>(bucket: &Bucket<P>, options: &Options) -> f64 {
+    let volume = || {
+        let mut volume = 1.0;
+        for i in 0..4 {
+            volume *= (bucket.high[i] - bucket.low[i]) as f64 + 1.0;
+        }
+        volume
+    };
+    match options.bucket_selection {
+        BucketSelection::Population => bucket.total as f64,
+        BucketSelection::Volume => volume(),
+        BucketSelection::PopulationVolume => bucket.total as f64 * volume(),
+        BucketSelection::Error => bucket.error,
+    }
+}
+
+// 分割する位置(bucket.colorsのインデックス)
+// 分割後のbucketが空にならないように1〜len-1の範囲にする
+fn cut_index<P: Point>(bucket: &Bucket<P>, options: &Options) -> usize {
+    let length = bucket.colors.len();
+    match options.cut_position {
+        CutPosition::Median => length.div_ceil(2),
+        // 累積のピクセル数が半分を超える位置で分割する
+        CutPosition::Population => {
+            let mut count: u64 = 0;
+            let mut index = length - 1;
+            for (i, sample) in bucket.colors.iter().enumerate() {
+                count += sample.color().count;
+                if count * 2 >= bucket.total {
+                    index = i + 1;
+                    break;
+                }
+            }
+            index.clamp(1, length - 1)
+        }
+        // 分割後の2つのbucketの二乗誤差の合計が最小になる位置で分割する
+        // 二乗の合計は分割位置によらないので、Σ(合計² / ピクセル数)が最大になる位置を探す
+        CutPosition::Optimal => {
+            let [weight_r, weight_g, weight_b] = options.weights();
+            let weights = [weight_r, weight_g, weight_b, options.alpha_weight].map(|w| w as f64 * w as f64);
+            let mut total_sum = [0.0f64; 4];
+            for sample in &bucket.colors {
+                for (i, value) in sample.coords().into_iter().enumerate() {
+                    total_sum[i] += value as f64 * sample.color().count as f64;
+                }
+            }
+
+            let mut count = 0.0;
+            let mut sum = [0.0f64; 4];
+            let mut best = f64::MIN;
+            let mut index = length.div_ceil(2);
+            for (i, sample) in bucket.colors[0..length - 1].iter().enumerate() {
+                count += sample.color().count as f64;
+                for (j, value) in sample.coords().into_iter().enumerate() {
+                    sum[j] += value as f64 * sample.color().count as f64;
+                }
+                let rest = bucket.total as f64 - count;
+                if count == 0.0 || rest == 0.0 {
+                    continue;
+                }
+                let mut score = 0.0;
+                for j in 0..4 {
+                    let rest_sum = total_sum[j] - sum[j];
+                    score += weights[j] * (sum[j] * sum[j] / count + rest_sum * rest_sum / rest);
+                }
+                if score > best {
+                    best = score;
+                    index = i + 1;
+                }
+            }
+            index
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::ColorSpace;
+
+    #[test]
+    fn test_average_color() {
+        let colors: Vec<Colors> = vec![
+            Colors::new(255, 0, 0, 255, 2),
+            Colors::new(0, 255, 0, 255, 1),
+            Colors::new(0, 0, 255, 255, 1),
+        ];
+        let result = average_color(colors.clone());
+        println!("{:?}", result);
+        assert_eq!(result, (128, 64, 64));
+    }
+
+    #[test]
+    fn test_fact() {
+        let colors: Vec<Colors> = vec![
+            Colors::new(255, 0, 0, 255, 2),
+            Colors::new(0, 255, 0, 255, 1),
+            Colors::new(0, 0, 255, 255, 1),
+        ];
+        let options = Options { size: 3, ..Options::default() };
+        let mut steps: Vec<Vec<BucketSnapshot>> = Vec::new();
+        let result = fact(vec![get_total_and_greatest_range_channel(colors.clone(), &options)], &options, &mut steps);
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[0], [
+            BucketSnapshot { total: 4, channel: 1, min_r: 0, min_g: 0, min_b: 0, max_r: 255, max_g: 255, max_b: 255, min_a: 255, max_a: 255, average: (128, 64, 64, 255) },
+        ]);
+        assert_eq!(steps[2], [
+            BucketSnapshot { total: 1, channel: 2, min_r: 0, min_g: 0, min_b: 255, max_r: 0, max_g: 0, max_b: 255, min_a: 255, max_a: 255, average: (0, 0, 255, 255) },
+            BucketSnapshot { total: 2, channel: 2, min_r: 255, min_g: 0, min_b: 0, max_r: 255, max_g: 0, max_b: 0, min_a: 255, max_a: 255, average: (255, 0, 0, 255) },
+            BucketSnapshot { total: 1, channel: 2, min_r: 0, min_g: 255, min_b: 0, max_r: 0, max_g: 255, max_b: 0, min_a: 255, max_a: 255, average: (0, 255, 0, 255) },
+        ]);
+        assert_eq!(result.len(), 3);
+    }
+
+    #[test]
+    fn test_weights() {
+        let colors: Vec<Colors> = vec![
+            Colors::new(0, 0, 0, 255, 1),
+            Colors::new(100, 0, 0, 255, 1),
+            Colors::new(0, 0, 110, 255, 1),
+        ];
+        let bucket = get_total_and_greatest_range_channel(colors.clone(), &Options::default());
+        assert_eq!(bucket.channel, Channel::R);
+
+        let options = Options { weights: Some([1.0, 1.0, 1.0]), ..Options::default() };
+        let bucket = get_total_and_greatest_range_channel(colors.clone(), &options);
+        assert_eq!(bucket.channel, Channel::B);
+    }
+
+    #[test]
+    fn test_alpha() {
+        let colors: Vec<Colors> = vec![
+            Colors::new(255, 0, 0, 255, 1),
+            Colors::new(250, 0, 0, 0, 1),
+            Colors::new(250, 0, 0, 50, 2),
+        ];
+        let bucket = get_total_and_greatest_range_channel(colors.clone(), &Options::default());
+        assert_eq!(bucket.channel, Channel::A);
+        assert_eq!((bucket.min_a, bucket.max_a), (0, 255));
+        // 透明な色は平均色のRGBに影響しない
+        assert_eq!(bucket.average, (254, 0, 0, 89));
+    }
+
+    #[test]
+    fn test_split_axis_variance() {
+        // Rは外れ値が1つだけで、Bは広く分布している
+        let colors: Vec<Colors> = vec![
+            Colors::new(0, 0, 0, 255, 100),
+            Colors::new(200, 0, 0, 255, 1),
+            Colors::new(0, 0, 150, 255, 100),
+        ];
+        let bucket = get_total_and_greatest_range_channel(colors.clone(), &Options::default());
+        assert_eq!(bucket.channel, Channel::R);
+
+        let options = Options { split_axis: SplitAxis::Variance, ..Options::default() };
+        let bucket = get_total_and_greatest_range_channel(colors.clone(), &options);
+        assert_eq!(bucket.channel, Channel::B);
+    }
+
+    #[test]
+    fn test_cut_position_population() {
+        let colors: Vec<Colors> = vec![
+            Colors::new(0, 0, 0, 255, 1),
+            Colors::new(10, 0, 0, 255, 1),
+            Colors::new(20, 0, 0, 255, 1),
+            Colors::new(200, 0, 0, 255, 10),
+            Colors::new(250, 0, 0, 255, 1),
+        ];
+        let bucket = get_total_and_greatest_range_channel(colors.clone(), &Options::default());
+        assert_eq!(cut_index(&bucket, &Options::default()), 3);
+
+        let options = Options { cut_position: CutPosition::Population, ..Options::default() };
+        assert_eq!(cut_index(&bucket, &options), 4);
+
+        // 1色に偏っていても空のbucketはできない
+        let colors: Vec<Colors> = vec![
+            Colors::new(0, 0, 0, 255, 1000000),
+            Colors::new(10, 0, 0, 255, 1),
+        ];
+        let bucket = get_total_and_greatest_range_channel(colors.clone(), &options);
+        assert_eq!(cut_index(&bucket, &options), 1);
+    }
+
+    #[test]
+    fn test_bucket_selection() {
+        // 1つ目は色の範囲が狭くピクセル数が多い、2つ目は範囲が広くピクセル数が少ない
+        let buckets = [
+            get_total_and_greatest_range_channel(vec![
+                Colors::new(0, 0, 0, 255, 50),
+                Colors::new(4, 0, 0, 255, 50),
+            ], &Options::default()),
+            get_total_and_greatest_range_channel(vec![
+                Colors::new(0, 0, 100, 255, 1),
+                Colors::new(0, 0, 255, 255, 1),
+            ], &Options::default()),
+        ];
+        assert!((buckets[0].error - 400.0 * 1.44).abs() < 0.001);
+
+        let split = |bucket_selection: BucketSelection| {
+            let options = Options { size: 3, bucket_selection, ..Options::default() };
+            let result = fact(buckets.iter().map(|b| get_total_and_greatest_range_channel(b.colors.clone(), &options)).collect(), &options, &mut Vec::new());
+            result.iter().map(|b| b.total).collect::<Vec<u64>>()
+        };
+        assert_eq!(split(BucketSelection::Population), [50, 50, 2]);
+        assert_eq!(split(BucketSelection::Volume), [100, 1, 1]);
+        assert_eq!(split(BucketSelection::PopulationVolume), [50, 50, 2]);
+        assert_eq!(split(BucketSelection::Error), [100, 1, 1]);
+    }
+
+    #[test]
+    fn test_cut_position_optimal() {
+        let colors: Vec<Colors> = vec![
+            Colors::new(0, 0, 0, 255, 5),
+            Colors::new(10, 0, 0, 255, 5),
+            Colors::new(20, 0, 0, 255, 5),
+            Colors::new(200, 0, 0, 255, 5),
+            Colors::new(210, 0, 0, 255, 5),
+            Colors::new(220, 0, 0, 255, 5),
+            Colors::new(230, 0, 0, 255, 5),
+        ];
+        let bucket = get_total_and_greatest_range_channel(colors.clone(), &Options::default());
+        assert_eq!(cut_index(&bucket, &Options::default()), 4);
+
+        // 色が離れている20と200の間で分割する
+        let options = Options { cut_position: CutPosition::Optimal, ..Options::default() };
+        assert_eq!(cut_index(&bucket, &options), 3);
+    }
+
+    #[test]
+    fn test_color_space() {
+        let colors: Vec<Colors> = vec![
+            Colors::new(0, 0, 0, 255, 1),
+            Colors::new(255, 255, 255, 255, 1),
+        ];
+        let bucket = get_total_and_greatest_range_channel(colors.clone(), &Options::default());
+        assert_eq!(bucket.average, (128, 128, 128, 255));
+
+        // 知覚的に均等な色空間では明度(LやL*)の中間で平均する
+        let options = Options { color_space: ColorSpace::Oklab, ..Options::default() };
+        let bucket = get_total_and_greatest_range_channel(samples(&colors, &options), &options);
+        assert_eq!(bucket.average, (99, 99, 99, 255));
+        let options = Options { color_space: ColorSpace::Lab, ..Options::default() };
+        let bucket = get_total_and_greatest_range_channel(samples(&colors, &options), &options);
+        assert_eq!(bucket.average, (119, 119, 119, 255));
+    }
+
+    #[test]
+    fn test_linear_average() {
+        let colors: Vec<Colors> = vec![
+            Colors::new(255, 0, 0, 255, 1),
+            Colors::new(0, 255, 0, 255, 1),
+        ];
+        assert_eq!(average(&colors), (128, 128, 0, 255));
+        // リニアで平均すると物理的な平均(0.5)になり、暗くならない
+        assert_eq!(average_linear(&colors), (188, 188, 0, 255));
+
+        let options = Options { linear_average: true, ..Options::default() };
+        let bucket = get_total_and_greatest_range_channel(colors.clone(), &options);
+        assert_eq!(bucket.average, (188, 188, 0, 255));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::palette::Palette;
+
+/// パレットから最も近い色を探すためのk-d木
+/// 各ノードの左右の子は配列の前半と後半にあり、配列の中央がノード自身になる
+#[derive(PartialEq, Clone, Debug)]
+pub(crate) struct Nearest {
+    points: Vec<Point>,
+    dimensions: usize,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+struct Point {
+    coords: [f32; 4],
+    // パレットのインデックス
+    index: usize,
+    // 子を分ける軸
+    axis: usize,
+}
+
+impl Nearest {
+    /// 透明用の色を除いたパレットの色からk-d木を作る
+    /// alphaがfalseの場合、アルファは距離に含めない
+    pub(crate) fn new(palette: &Palette, alpha: bool) -> Nearest {
+        let dimensions = if alpha { 4 } else { 3 };
+        let mut points: Vec<Point> = palette.colors().iter().enumerate()
+            .filter(|(index, _)| palette.transparent_index() != Some(*index))
+            .map(|(index, c)| Point { coords: [c.r as f32, c.g as f32, c.b as f32, c.a as f32], index, axis: 0 })
+            .collect();
+        build(&mut points, dimensions);
+        Nearest { points, dimensions }
+    }
+
+    /// 座標の一覧からk-d木を作る(インデックスは座標の位置)
+    pub(crate) fn from_coords(coords: &[[f32; 4]], alpha: bool) -> Nearest {
+        let dimensions = if alpha { 4 } else { 3 };
+        let mut points: Vec<Point> = coords.iter().enumerate().map(|(index, &coords)| Point { coords, index, axis: 0 }).collect();
+        build(&mut points, dimensions);
+        Nearest { points, dimensions }
+    }
+
+    /// 最も近い色のインデックス(距離が同じ場合はインデックスが小さい方)
+    pub(crate) fn find(&self, color: [f32; 4]) -> usize {
+        self.find_with_distance(color).0
+    }
+
+    /// 最も近い色のインデックスと、その色までの距離の二乗
+    pub(crate) fn find_with_distance(&self, color: [f32; 4]) -> (usize, f32) {
+        let mut best = (f32::MAX, 0);
+        self.search(0, self.points.len(), &color, &mut best);
+        (best.1, best.0)
+    }
+
+    /// 2色の距離の二乗(alphaがfalseの場合、アルファは含めない)
+    pub(crate) fn distance(a: [f32; 4], b: [f32; 4], alpha: bool) -> f32 {
+        let dimensions = if alpha { 4 } else { 3 };
+        (0..dimensions).map(|i| (a[i] - b[i]).powi(2)).sum()
+    }
+
+    fn search(&self, start: usize, end: usize, color: &[f32; 4], best: &mut (f32, usize)) {
+        if start >= end {
+            return;
+        }
+        let middle = (start + end) / 2;
+        let point = &self.points[middle];
+        let distance = Nearest::distance(point.coords, *color, self.dimensions == 4);
+        if distance < best.0 || (distance == best.0 && point.index < best.1) {
+            *best = (distance, point.index);
+        }
+
+        // 近い側から探し、反対側は軸までの距離が今の最短距離以下の場合だけ探す
+        let diff = color[point.axis] - point.coords[point.axis];
+        let (near, far) = if diff < 0.0 {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+        self.search(near.0, near.1, color, best);
+        if diff * diff <= best.0 {
+            self.search(far.0, far.1, color, best);
+        }
+    }
+}
+
+// 範囲が最も広い軸で並べ替え、中央で分ける
+fn build(points: &mut [Point], dimensions: usize) {
+    if points.len() <= 1 {
+        return;
+    }
+    let mut axis = 0;
+    let mut widest = -1.0;
+    for i in 0..dimensions {
+        let min = points.iter().map(|p| p.coords[i]).fold(f32::MAX, f32::min);
+        let max = points.iter().map(|p| p.coords[i]).fold(f32::MIN, f32::max);
+        if max - min > widest {
+            axis = i;
+            widest = max - min;
+        }
+    }
+    points.sort_by(|a, b| a.coords[axis].total_cmp(&b.coords[axis]));
+
+    let middle = points.len() / 2;
+    points[middle].axis = axis;
+    build(&mut points[..middle], dimensions);
+    build(&mut points[middle + 1..], dimensions);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::Colors;
+
+    #[test]
+    fn test_find() {
+        let mut palette = Palette::new(vec![Colors::new(0, 0, 0, 255, 1), Colors::new(255, 255, 255, 255, 1)]);
+        let nearest = Nearest::new(&palette, false);
+        assert_eq!(nearest.find([100.0, 100.0, 100.0, 255.0]), 0);
+        assert_eq!(nearest.find([200.0, 100.0, 200.0, 255.0]), 1);
+
+        // 透明用の色は選ばれない
+        palette.push_transparent(1);
+        assert_eq!(Nearest::new(&palette, true).find([0.0, 0.0, 0.0, 0.0]), 0);
+    }
+
+    #[test]
+    fn test_find_same_as_linear_search() {
+        let colors: Vec<Colors> = (0..64u32).map(|i| Colors::new((i * 37 % 256) as u8, (i * 91 % 256) as u8, (i * 53 % 256) as u8, 255, 1)).collect();
+        let palette = Palette::new(colors.clone());
+        let nearest = Nearest::new(&palette, false);
+        for i in 0..512u32 {
+            let color = [(i * 13 % 256) as f32, (i * 29 % 256) as f32, (i * 7 % 256) as f32, 255.0];
+            let distance = |c: &Colors| (c.r as f32 - color[0]).powi(2) + (c.g as f32 - color[1]).powi(2) + (c.b as f32 - color[2]).powi(2);
+            let expected = (0..colors.len()).min_by(|&a, &b| distance(&colors[a]).total_cmp(&distance(&colors[b]))).unwrap();
+            assert_eq!(nearest.find(color), expected);
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use crate::histogram::Colors;
+
+/// 八分木(アルファを考慮する場合は十六分木)による減色
+/// 葉の数が上限を超えるたびに深い節を葉にまとめるので、使うメモリは色の種類数によらず上限で抑えられる
+#[derive(Clone, Debug)]
+pub(crate) struct Octree {
+    nodes: Vec<Node>,
+    // 深さごとの、子を持つ節
+    reducible: Vec<Vec<usize>>,
+    // まとめた後に再利用できる節
+    free: Vec<usize>,
+    leaves: usize,
+    max_leaves: usize,
+    alpha: bool,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    // 0は子がないことを表す(根は子にならない)
+    children: [usize; 16],
+    leaf: bool,
+    // ピクセル数で重み付けした合計 [r, g, b, a]
+    sum: [u64; 4],
+    count: u64,
+    // palette()で付けるパレットのインデックス
+    index: usize,
+}
+
+const MAX_DEPTH: usize = 8;
+
+impl Node {
+    fn new(leaf: bool) -> Node {
+        Node { children: [0; 16], leaf, sum: [0; 4], count: 0, index: 0 }
+    }
+}
+
+impl Octree {
+    /// max_leavesは葉の数(パレットの色数)の上限
+    pub(crate) fn new(max_leaves: usize, alpha: bool) -> Octree {
+        Octree {
+            nodes: vec![Node::new(false)],
+            reducible: vec![Vec::new(); MAX_DEPTH],
+            free: Vec::new(),
+            leaves: 0,
+            max_leaves: max_leaves.max(1),
+            alpha,
+        }
+    }
+
+    /// 色を追加し、葉の数が上限を超えたら節をまとめる
+    pub(crate) fn insert(&mut self, color: &Colors) {
+        let mut node = 0;
+        for depth in 0..MAX_DEPTH {
+            if self.nodes[node].leaf {
+                break;
+            }
+            let child = self.child_index(color, depth);
+            if self.nodes[node].children[child] == 0 {
+                let leaf = depth + 1 == MAX_DEPTH;
+                let new_node = match self.free.pop() {
+                    Some(free) => {
+                        self.nodes[free] = Node::new(leaf);
+                        free
+                    }
+                    None => {
+                        self.nodes.push(Node::new(leaf));
+                        self.nodes.len() - 1
+                    }
+                };
+                self.nodes[node].children[child] = new_node;
+                if leaf {
+                    self.leaves += 1;
+                } else {
+                    self.reducible[depth + 1].push(new_node);
+                }
+            }
+            node = self.nodes[node].children[child];
+        }
+
+        let node = &mut self.nodes[node];
+        for (i, value) in [color.r, color.g, color.b, color.a].into_iter().enumerate() {
+            node.sum[i] += value as u64 * color.count;
+        }
+        node.count += color.count;
+
+        while self.leaves > self.max_leaves {
+            self.reduce();
+        }
+    }
+
+    // 最も深い節のうち、ピクセル数が最も少ない節の子を1つの葉にまとめる
+    // 全ての子をまとめると葉の数が上限より少なくなる場合は、ピクセル数が少ない子だけをまとめる
+    fn reduce(&mut self) {
+        let depth = (0..MAX_DEPTH).rev().find(|&depth| !self.reducible[depth].is_empty()).unwrap_or(0);
+        let position = if self.reducible[depth].is_empty() {
+            None
+        } else {
+            let count = |node: usize| self.subtree_count(node);
+            self.reducible[depth].iter().enumerate().min_by_key(|(_, &node)| count(node)).map(|(position, _)| position)
+        };
+        let node = position.map_or(0, |position| self.reducible[depth][position]);
+
+        // 最も深い節なので、子は全て葉になっている
+        let mut children = self.children(node);
+        let excess = self.leaves - self.max_leaves;
+        if children.len() > excess + 1 {
+            children.sort_by_key(|&child| self.nodes[child].count);
+            let (merged, target) = (&children[..excess], children[excess]);
+            for &child in merged {
+                let (sum, count) = (self.nodes[child].sum, self.nodes[child].count);
+                for (c, value) in sum.into_iter().enumerate() {
+                    self.nodes[target].sum[c] += value;
+                }
+                self.nodes[target].count += count;
+                // まとめた子の色もまとめた先の葉に入るようにする
+                for slot in self.nodes[node].children.iter_mut().filter(|slot| **slot == child) {
+                    *slot = target;
+                }
+                self.free.push(child);
+            }
+            self.leaves -= excess;
+            return;
+        }
+
+        if let Some(position) = position {
+            self.reducible[depth].remove(position);
+        }
+        for &child in &children {
+            let (sum, count) = (self.nodes[child].sum, self.nodes[child].count);
+            for (c, value) in sum.into_iter().enumerate() {
+                self.nodes[node].sum[c] += value;
+            }
+            self.nodes[node].count += count;
+            self.free.push(child);
+        }
+        self.nodes[node].children = [0; 16];
+        self.nodes[node].leaf = true;
+        self.leaves = self.leaves + 1 - children.len();
+    }
+
+    // 重複を除いた子(ピクセル数が少ない子をまとめた場合は、複数の位置が同じ子を指す)
+    fn children(&self, node: usize) -> Vec<usize> {
+        let mut children: Vec<usize> = Vec::new();
+        for &child in &self.nodes[node].children {
+            if child != 0 && !children.contains(&child) {
+                children.push(child);
+            }
+        }
+        children
+    }
+
+    fn subtree_count(&self, node: usize) -> u64 {
+        self.nodes[node].count + self.children(node).into_iter().map(|child| self.subtree_count(child)).sum::<u64>()
+    }
+
+    // 各チャンネルの上位からdepth番目のビットで子を選ぶ
+    fn child_index(&self, color: &Colors, depth: usize) -> usize {
+        let bit = |value: u8| ((value >> (7 - depth)) & 1) as usize;
+        let index = (bit(color.r) << 2) | (bit(color.g) << 1) | bit(color.b);
+        if self.alpha { (index << 1) | bit(color.a) } else { index }
+    }
+
+    /// 葉ごとの平均色を並べたパレット(各色のピクセル数を含む)
+    pub(crate) fn palette(&mut self) -> Vec<Colors> {
+        let mut palette: Vec<Colors> = Vec::new();
+        let mut stack: Vec<usize> = vec![0];
+        while let Some(node) = stack.pop() {
+            if self.nodes[node].leaf {
+                let node = &mut self.nodes[node];
+                if node.count == 0 {
+                    continue;
+                }
+                let [r, g, b, a] = node.sum.map(|sum| ((sum as f64 / node.count as f64).round()) as u8);
+                node.index = palette.len();
+                palette.push(Colors::new(r, g, b, a, node.count));
+                continue;
+            }
+            stack.extend(self.children(node).into_iter().rev());
+        }
+        palette
+    }
+
+    /// 色が含まれる葉のパレットのインデックス(palette()の後に使う)
+    pub(crate) fn index(&self, color: &Colors) -> usize {
+        let mut node = 0;
+        for depth in 0..MAX_DEPTH {
+            if self.nodes[node].leaf {
+                break;
+            }
+            node = self.nodes[node].children[self.child_index(color, depth)];
+        }
+        self.nodes[node].index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_octree() {
+        let colors: Vec<Colors> = vec![
+            Colors::new(0, 0, 0, 255, 1),
+            Colors::new(10, 0, 0, 255, 3),
+            Colors::new(255, 255, 255, 255, 2),
+            Colors::new(250, 250, 250, 255, 2),
+        ];
+        let mut octree = Octree::new(2, false);
+        for color in &colors {
+            octree.insert(color);
+        }
+        assert_eq!(octree.palette(), [Colors::new(8, 0, 0, 255, 4), Colors::new(253, 253, 253, 255, 4)]);
+        assert_eq!(colors.iter().map(|color| octree.index(color)).collect::<Vec<usize>>(), [0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_fewer_leaves_than_octants() {
+        // 8つの象限の全てに色がある場合も、根の子を全てまとめて1色にしない
+        let corners: Vec<Colors> = (0..8u32).map(|i| {
+            let value = |bit: u32| if i & bit == 0 { 0 } else { 255 };
+            Colors::new(value(4), value(2), value(1), 255, i as u64 + 1)
+        }).collect();
+        for size in [2, 4, 7] {
+            let mut octree = Octree::new(size, false);
+            for color in &corners {
+                octree.insert(color);
+            }
+            let palette = octree.palette();
+            assert_eq!(palette.len(), size);
+            assert_eq!(palette.iter().map(|color| color.count).sum::<u64>(), 36);
+            // まとめた色もパレットのどれかに対応する
+            let mut indices: Vec<usize> = corners.iter().map(|color| octree.index(color)).collect();
+            indices.sort();
+            indices.dedup();
+            assert_eq!(indices, (0..size).collect::<Vec<usize>>());
+        }
+
+        // ピクセル数が最も少ない2色だけがまとまる
+        let mut octree = Octree::new(7, false);
+        for color in &corners {
+            octree.insert(color);
+        }
+        let palette = octree.palette();
+        assert_eq!(octree.index(&corners[0]), octree.index(&corners[1]));
+        assert_eq!(palette[octree.index(&corners[0])], Colors::new(0, 0, 170, 255, 3));
+        for color in &corners[2..] {
+            assert_eq!(palette[octree.index(color)], *color);
+        }
+    }
+
+    #[test]
+    fn test_max_leaves() {
+        // 葉の数は常に上限以下
+        let mut octree = Octree::new(16, false);
+        for i in 0..4096u32 {
+            octree.insert(&Colors::new((i * 7 % 256) as u8, (i * 13 % 256) as u8, (i * 31 % 256) as u8, 255, 1));
+            assert!(octree.leaves <= 16);
+        }
+        // まとめた節を再利用するので、節の数も増え続けない
+        assert!(octree.nodes.len() < 16 * MAX_DEPTH * 2);
+        let palette = octree.palette();
+        assert!(palette.len() <= 16);
+        assert_eq!(palette.iter().map(|color| color.count).sum::<u64>(), 4096);
+    }
+}
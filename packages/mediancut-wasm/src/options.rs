@@ -0,0 +1,361 @@
+use serde::Deserialize;
+use crate::error::Error;
+use crate::presets::FixedPalette;
+
+/// 減色のアルゴリズム
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Algorithm {
+    /// メディアンカット
+    MedianCut,
+    /// 八分木
+    Octree,
+    /// Wuの方法(分散が最小になるように分割する)
+    Wu,
+}
+
+/// 分割するチャンネルの選び方
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum SplitAxis {
+    /// 最大値と最小値の差が最も大きいチャンネル
+    Range,
+    /// ピクセル数で重み付けした分散が最も大きいチャンネル
+    Variance,
+}
+
+/// bucketを分割する位置
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum CutPosition {
+    /// 色の種類数の中央
+    Median,
+    /// ピクセル数の中央
+    Population,
+    /// 分割後の二乗誤差の合計が最小になる位置
+    Optimal,
+}
+
+/// 次に分割するbucketの選び方
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum BucketSelection {
+    /// ピクセル数が最大のbucket
+    Population,
+    /// 色の範囲(最小値と最大値で囲まれた体積)が最大のbucket
+    Volume,
+    /// ピクセル数 × 体積が最大のbucket
+    PopulationVolume,
+    /// 平均色との二乗誤差の合計が最大のbucket
+    Error,
+}
+
+/// 分割や平均色の計算に使う色空間
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ColorSpace {
+    /// sRGBのまま
+    Srgb,
+    /// OKLab
+    Oklab,
+    /// CIELAB(D65)
+    Lab,
+}
+
+/// パレットの色に置き換える時のディザリング
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Dither {
+    /// ディザリングしない(元の色が含まれるbucketの平均色にする)
+    None,
+    /// Floyd–Steinbergの誤差拡散
+    FloydSteinberg,
+    /// Atkinsonの誤差拡散(誤差の3/4だけを拡散する)
+    Atkinson,
+    /// Sierra(3行)の誤差拡散
+    Sierra,
+    /// Stuckiの誤差拡散
+    Stucki,
+    /// Bayer行列による組織的ディザリング(ピクセルごとに独立して計算できる)
+    Bayer,
+    /// ブルーノイズを閾値にしたディザリング(ピクセルごとに独立して計算できる)
+    BlueNoise,
+    /// Knollのパターンディザリング(色ごとにパレットの色の組み合わせを選び、Bayer行列で並べる)
+    Pattern,
+}
+
+/// 減色のオプション
+/// 省略した値は`Options::default()`と同じになり、知らないキーはエラーにする
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields, default)]
+pub struct Options {
+    /// 減色後の最大の色数
+    pub size: u8,
+    /// 減色のアルゴリズム
+    pub algorithm: Algorithm,
+    /// 各チャンネルの有効ビット数(5〜8)
+    pub precision: u8,
+    /// 分割するチャンネルを選ぶ時に、各チャンネルの範囲にかける係数 [r, g, b]
+    /// 省略した場合は色空間ごとの既定値になる(`Options::weights`)
+    pub weights: Option<[f32; 3]>,
+    /// アルファを4番目のチャンネルとして減色する
+    pub alpha: bool,
+    /// 分割するチャンネルを選ぶ時に、アルファの範囲にかける係数
+    pub alpha_weight: f32,
+    /// 完全に透明なピクセルを色数から除外し、透明用にパレットを1つ確保する
+    pub transparent: bool,
+    /// 半透明なピクセルを合成してから色数を数える時の背景色 [r, g, b]
+    pub matte: Option<[u8; 3]>,
+    /// 分割するチャンネルの選び方
+    pub split_axis: SplitAxis,
+    /// bucketを分割する位置
+    pub cut_position: CutPosition,
+    /// 次に分割するbucketの選び方
+    pub bucket_selection: BucketSelection,
+    /// 分割や平均色の計算に使う色空間(`weights`はこの色空間の各チャンネルにかける)
+    pub color_space: ColorSpace,
+    /// sRGBをリニアに戻してから平均色を求める(`colorSpace`が`srgb`の場合のみ)
+    pub linear_average: bool,
+    /// パレットの色に置き換える時のディザリング
+    pub dither: Dither,
+    /// 誤差拡散で奇数行を右から左に走査する
+    pub serpentine: bool,
+    /// 拡散する誤差にかける係数(0〜1)、パターンディザリングでは色の組み合わせを選ぶ時の誤差にかける
+    pub dither_strength: f32,
+    /// Bayer行列の大きさ(2, 4, 8)、パターンディザリングでは組み合わせる色の数がこの2乗になる
+    pub bayer_size: u8,
+    /// 組織的ディザリングとブルーノイズで閾値を加える幅(0〜255)
+    pub dither_spread: f32,
+    /// 指定した場合はメディアンカットで減色せず、このパレットの最も近い色に置き換える
+    pub palette: Option<FixedPalette>,
+    /// 必ずパレットに入れる色 [[r, g, b], ...](sizeに含まれ、残りの色をメディアンカットで決める)
+    pub locked_colors: Vec<[u8; 3]>,
+    /// メディアンカットのパレットを初期値にしてk-meansで調整する最大の回数(0の場合は調整しない)
+    pub kmeans_iterations: u32,
+    /// k-meansで全ての色の移動量がこの値未満になったら終了する
+    pub kmeans_threshold: f32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            size: 16,
+            algorithm: Algorithm::MedianCut,
+            precision: 8,
+            weights: None,
+            alpha: false,
+            alpha_weight: 1.0,
+            transparent: false,
+            matte: None,
+            split_axis: SplitAxis::Range,
+            cut_position: CutPosition::Median,
+            bucket_selection: BucketSelection::Population,
+            color_space: ColorSpace::Srgb,
+            linear_average: false,
+            dither: Dither::None,
+            serpentine: false,
+            dither_strength: 1.0,
+            bayer_size: 4,
+            dither_spread: 64.0,
+            palette: None,
+            locked_colors: Vec::new(),
+            kmeans_iterations: 0,
+            kmeans_threshold: 0.5,
+        }
+    }
+}
+
+impl Options {
+    /// 各チャンネルにかける係数(`weights`を省略した場合は色空間ごとの既定値)
+    pub fn weights(&self) -> [f32; 3] {
+        match (self.weights, self.color_space) {
+            (Some(weights), _) => weights,
+            // 目は赤と緑が認識しやすいのでRとGに係数をかける
+            (None, ColorSpace::Srgb) => [1.2, 1.2, 1.0],
+            // OKLabやCIELABは知覚的に均等なので係数をかけない
+            (None, _) => [1.0, 1.0, 1.0],
+        }
+    }
+
+    /// JSONからオプションを読み込む
+    pub fn from_json(json: &str) -> Result<Options, Error> {
+        let options: Options = serde_json::from_str(json).map_err(|e| Error::InvalidOptions(e.to_string()))?;
+        options.validate()?;
+        Ok(options)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if !(5..=8).contains(&self.precision) {
+            return Err(Error::InvalidOptions(format!("precision must be between 5 and 8, got {}", self.precision)));
+        }
+        if let Some(weights) = self.weights {
+            if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+                return Err(Error::InvalidOptions(format!("weights must be finite and non-negative, got {:?}", weights)));
+            }
+        }
+        if !self.alpha_weight.is_finite() || self.alpha_weight < 0.0 {
+            return Err(Error::InvalidOptions(format!("alphaWeight must be finite and non-negative, got {}", self.alpha_weight)));
+        }
+        if !(0.0..=1.0).contains(&self.dither_strength) {
+            return Err(Error::InvalidOptions(format!("ditherStrength must be between 0 and 1, got {}", self.dither_strength)));
+        }
+        if ![2, 4, 8].contains(&self.bayer_size) {
+            return Err(Error::InvalidOptions(format!("bayerSize must be 2, 4 or 8, got {}", self.bayer_size)));
+        }
+        if !(0.0..=255.0).contains(&self.dither_spread) {
+            return Err(Error::InvalidOptions(format!("ditherSpread must be between 0 and 255, got {}", self.dither_spread)));
+        }
+        if !self.kmeans_threshold.is_finite() || self.kmeans_threshold < 0.0 {
+            return Err(Error::InvalidOptions(format!("kmeansThreshold must be finite and non-negative, got {}", self.kmeans_threshold)));
+        }
+        // 固定のパレットを使う場合は減色しないので、減色のオプションは無視されないようにエラーにする
+        if self.palette.is_some() {
+            let defaults = Options::default();
+            let quantizer_only = [
+                ("size", self.size != defaults.size),
+                ("algorithm", self.algorithm != defaults.algorithm),
+                ("weights", self.weights.is_some()),
+                ("alphaWeight", self.alpha_weight != defaults.alpha_weight),
+                ("splitAxis", self.split_axis != defaults.split_axis),
+                ("cutPosition", self.cut_position != defaults.cut_position),
+                ("bucketSelection", self.bucket_selection != defaults.bucket_selection),
+                ("colorSpace", self.color_space != defaults.color_space),
+                ("linearAverage", self.linear_average),
+                ("lockedColors", !self.locked_colors.is_empty()),
+                ("kmeansIterations", self.kmeans_iterations != defaults.kmeans_iterations),
+                ("kmeansThreshold", self.kmeans_threshold != defaults.kmeans_threshold),
+            ];
+            if let Some((name, _)) = quantizer_only.iter().find(|(_, set)| *set) {
+                return Err(Error::InvalidOptions(format!("{name} does not apply when palette is set")));
+            }
+        }
+        // Wuの方法はRGBのモーメントだけで分割するので、アルファの違う色を区別できない
+        if self.algorithm == Algorithm::Wu && self.alpha {
+            return Err(Error::InvalidOptions("alpha is not supported by the wu algorithm".to_string()));
+        }
+        // メディアンカットでしか使わないオプションは、他のアルゴリズムでは無視されないようにエラーにする
+        if self.algorithm != Algorithm::MedianCut {
+            let defaults = Options::default();
+            let median_cut_only = [
+                ("weights", self.weights.is_some()),
+                ("alphaWeight", self.alpha_weight != defaults.alpha_weight),
+                ("splitAxis", self.split_axis != defaults.split_axis),
+                ("cutPosition", self.cut_position != defaults.cut_position),
+                ("bucketSelection", self.bucket_selection != defaults.bucket_selection),
+                ("linearAverage", self.linear_average),
+                ("lockedColors", !self.locked_colors.is_empty()),
+            ];
+            if let Some((name, _)) = median_cut_only.iter().find(|(_, set)| *set) {
+                return Err(Error::InvalidOptions(format!("{name} only applies to the medianCut algorithm")));
+            }
+            // k-meansは色空間を使う
+            if self.color_space != defaults.color_space && self.kmeans_iterations == 0 {
+                return Err(Error::InvalidOptions("colorSpace only applies to the medianCut algorithm or when kmeansIterations is set".to_string()));
+            }
+        }
+        // 透明用の色もsizeに含める
+        if self.transparent && self.palette.is_none() && self.size < 2 {
+            return Err(Error::InvalidOptions(format!("size must be at least 2 when transparent is set, got {}", self.size)));
+        }
+        let available = (self.size as usize).saturating_sub(usize::from(self.transparent));
+        if self.locked_colors.len() > available {
+            let limit = if self.transparent { format!("size - 1 ({}) colors when transparent is set", available) } else { format!("size ({}) colors", available) };
+            return Err(Error::InvalidOptions(format!("lockedColors must not have more than {}, got {}", limit, self.locked_colors.len())));
+        }
+        if let Some(palette) = &self.palette {
+            let max = if self.transparent { 255 } else { 256 };
+            let length = palette.colors().len();
+            if length == 0 || length > max {
+                return Err(Error::InvalidOptions(format!("palette must have between 1 and {max} colors, got {length}")));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::Preset;
+
+    #[test]
+    fn test_from_json() {
+        assert_eq!(Options::from_json("{}").unwrap(), Options::default());
+        assert_eq!(Options::from_json(r#"{"size": 12, "precision": 5}"#).unwrap(), Options { size: 12, precision: 5, ..Options::default() });
+        assert_eq!(Options::from_json(r#"{"algorithm": "octree"}"#).unwrap().algorithm, Algorithm::Octree);
+        assert_eq!(Options::from_json(r#"{"algorithm": "wu"}"#).unwrap().algorithm, Algorithm::Wu);
+        assert_eq!(Options::from_json(r#"{"weights": [1, 1, 1]}"#).unwrap().weights, Some([1.0, 1.0, 1.0]));
+        assert!(Options::from_json(r#"{"alpha": true, "alphaWeight": 2}"#).unwrap().alpha);
+        assert_eq!(Options::from_json(r#"{"matte": [255, 255, 255]}"#).unwrap().matte, Some([255, 255, 255]));
+        assert_eq!(Options::from_json(r#"{"splitAxis": "variance"}"#).unwrap().split_axis, SplitAxis::Variance);
+        assert_eq!(Options::from_json(r#"{"cutPosition": "population"}"#).unwrap().cut_position, CutPosition::Population);
+        assert_eq!(Options::from_json(r#"{"cutPosition": "optimal"}"#).unwrap().cut_position, CutPosition::Optimal);
+        assert_eq!(Options::from_json(r#"{"bucketSelection": "populationVolume"}"#).unwrap().bucket_selection, BucketSelection::PopulationVolume);
+        assert_eq!(Options::from_json(r#"{"colorSpace": "oklab"}"#).unwrap().color_space, ColorSpace::Oklab);
+        // 係数を省略した場合、sRGBではRとGに係数をかけ、知覚的な色空間では均等にする
+        assert_eq!(Options::default().weights(), [1.2, 1.2, 1.0]);
+        assert_eq!(Options::from_json(r#"{"colorSpace": "oklab"}"#).unwrap().weights(), [1.0, 1.0, 1.0]);
+        assert_eq!(Options::from_json(r#"{"colorSpace": "lab", "weights": [2, 1, 1]}"#).unwrap().weights(), [2.0, 1.0, 1.0]);
+        assert!(Options::from_json(r#"{"linearAverage": true}"#).unwrap().linear_average);
+        assert_eq!(Options::from_json(r#"{"dither": "floydSteinberg", "serpentine": true, "ditherStrength": 0.8}"#).unwrap(), Options { dither: Dither::FloydSteinberg, serpentine: true, dither_strength: 0.8, ..Options::default() });
+        assert_eq!(Options::from_json(r#"{"dither": "blueNoise"}"#).unwrap().dither, Dither::BlueNoise);
+        assert_eq!(Options::from_json(r#"{"dither": "pattern"}"#).unwrap().dither, Dither::Pattern);
+        assert_eq!(Options::from_json(r#"{"kmeansIterations": 8, "kmeansThreshold": 1}"#).unwrap(), Options { kmeans_iterations: 8, kmeans_threshold: 1.0, ..Options::default() });
+        assert_eq!(Options::from_json(r#"{"lockedColors": [[0, 0, 0]]}"#).unwrap().locked_colors, [[0, 0, 0]]);
+        assert_eq!(Options::from_json(r#"{"palette": "pico8"}"#).unwrap().palette, Some(FixedPalette::Preset(Preset::Pico8)));
+        assert_eq!(Options::from_json(r#"{"palette": [[0, 0, 0], [255, 255, 255]]}"#).unwrap().palette, Some(FixedPalette::Colors(vec![[0, 0, 0], [255, 255, 255]])));
+        assert_eq!(Options::from_json(r#"{"dither": "bayer", "bayerSize": 8, "ditherSpread": 32}"#).unwrap(), Options { dither: Dither::Bayer, bayer_size: 8, dither_spread: 32.0, ..Options::default() });
+    }
+
+    #[test]
+    fn test_from_json_error() {
+        let error = Options::from_json(r#"{"size": 12, "strict": false}"#).unwrap_err();
+        assert!(error.to_string().starts_with("invalid options: unknown field `strict`"));
+
+        let error = Options::from_json(r#"{"precision": 4}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: precision must be between 5 and 8, got 4");
+
+        let error = Options::from_json(r#"{"weights": [1, -1, 1]}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: weights must be finite and non-negative, got [1.0, -1.0, 1.0]");
+
+        let error = Options::from_json(r#"{"ditherStrength": 1.5}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: ditherStrength must be between 0 and 1, got 1.5");
+
+        let error = Options::from_json(r#"{"size": 1, "lockedColors": [[0, 0, 0], [255, 255, 255]]}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: lockedColors must not have more than size (1) colors, got 2");
+
+        let error = Options::from_json(r#"{"size": 1, "transparent": true}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: size must be at least 2 when transparent is set, got 1");
+
+        let error = Options::from_json(r#"{"size": 2, "transparent": true, "lockedColors": [[0, 0, 0], [255, 255, 255]]}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: lockedColors must not have more than size - 1 (1) colors when transparent is set, got 2");
+
+        let error = Options::from_json(r#"{"palette": "pico8", "lockedColors": [[0, 0, 0]]}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: lockedColors does not apply when palette is set");
+
+        let error = Options::from_json(r#"{"palette": "pico8", "algorithm": "wu"}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: algorithm does not apply when palette is set");
+
+        let error = Options::from_json(r#"{"palette": "pico8", "kmeansIterations": 4}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: kmeansIterations does not apply when palette is set");
+
+        let error = Options::from_json(r#"{"palette": []}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: palette must have between 1 and 256 colors, got 0");
+
+        let error = Options::from_json(r#"{"algorithm": "wu", "alpha": true}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: alpha is not supported by the wu algorithm");
+
+        let error = Options::from_json(r#"{"algorithm": "octree", "lockedColors": [[0, 0, 0]]}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: lockedColors only applies to the medianCut algorithm");
+
+        let error = Options::from_json(r#"{"algorithm": "wu", "cutPosition": "optimal"}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: cutPosition only applies to the medianCut algorithm");
+
+        let error = Options::from_json(r#"{"algorithm": "wu", "colorSpace": "oklab"}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: colorSpace only applies to the medianCut algorithm or when kmeansIterations is set");
+        assert!(Options::from_json(r#"{"algorithm": "wu", "colorSpace": "oklab", "kmeansIterations": 4}"#).is_ok());
+
+        let error = Options::from_json(r#"{"dither": "bayer", "bayerSize": 3}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid options: bayerSize must be 2, 4 or 8, got 3");
+    }
+}
@@ -0,0 +1,167 @@
+use serde::Serialize;
+use crate::error::Error;
+use crate::histogram::Colors;
+
+/// 減色後の色の一覧
+/// 各色のcountはその色で置き換えられるピクセル数
+#[derive(PartialEq, Clone, Debug, Serialize)]
+#[serde(transparent)]
+pub struct Palette {
+    colors: Vec<Colors>,
+    #[serde(skip)]
+    transparent: Option<usize>,
+}
+
+impl Palette {
+    pub fn new(colors: Vec<Colors>) -> Palette {
+        Palette { colors, transparent: None }
+    }
+
+    /// [r, g, b, r, g, b, ...] からパレットを作る(ピクセル数は0、アルファは255とする)
+    /// 長さが3の倍数でない場合はエラーにする
+    pub fn from_rgb(rgb: &[u8]) -> Result<Palette, Error> {
+        if rgb.len() % 3 != 0 {
+            return Err(Error::InvalidInput(format!("palette must have a multiple of 3 values, got {}", rgb.len())));
+        }
+        let colors = rgb.chunks_exact(3).map(|c| Colors::new(c[0], c[1], c[2], 255, 0)).collect();
+        Ok(Palette { colors, transparent: None })
+    }
+
+    /// [r, g, b, a, r, g, b, a, ...] からパレットを作る(ピクセル数は0とする)
+    /// 長さが4の倍数でない場合はエラーにする
+    pub fn from_rgba(rgba: &[u8]) -> Result<Palette, Error> {
+        if rgba.len() % 4 != 0 {
+            return Err(Error::InvalidInput(format!("palette must have a multiple of 4 values, got {}", rgba.len())));
+        }
+        let colors = rgba.chunks_exact(4).map(|c| Colors::new(c[0], c[1], c[2], c[3], 0)).collect();
+        Ok(Palette { colors, transparent: None })
+    }
+
+    /// 各色のアルファを置き換える(PNGのtRNSなど、RGBとは別に持っているアルファ向け)
+    /// alphaの長さがパレットの色数と違う場合はエラーにする
+    pub fn with_alpha(mut self, alpha: &[u8]) -> Result<Palette, Error> {
+        if alpha.len() != self.colors.len() {
+            return Err(Error::InvalidInput(format!("alpha must have {} values, got {}", self.colors.len(), alpha.len())));
+        }
+        for (color, &a) in self.colors.iter_mut().zip(alpha) {
+            color.a = a;
+        }
+        Ok(self)
+    }
+
+    /// 透明用の色を末尾に追加する
+    pub(crate) fn push_transparent(&mut self, count: u64) {
+        self.transparent = Some(self.colors.len());
+        self.colors.push(Colors::new(0, 0, 0, 0, count));
+    }
+
+    /// 透明用に確保した色のインデックス
+    pub fn transparent_index(&self) -> Option<usize> {
+        self.transparent
+    }
+
+    pub fn colors(&self) -> &[Colors] {
+        &self.colors
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    /// [r, g, b, r, g, b, ...] の形式で返す(GIFやPNGのPLTE向け)
+    pub fn to_rgb(&self) -> Vec<u8> {
+        let mut rgb: Vec<u8> = Vec::with_capacity(self.colors.len() * 3);
+        for color in &self.colors {
+            rgb.push(color.r);
+            rgb.push(color.g);
+            rgb.push(color.b);
+        }
+        rgb
+    }
+
+    /// [r, g, b, a, r, g, b, a, ...] の形式で返す
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut rgba: Vec<u8> = Vec::with_capacity(self.colors.len() * 4);
+        for color in &self.colors {
+            rgba.push(color.r);
+            rgba.push(color.g);
+            rgba.push(color.b);
+            rgba.push(color.a);
+        }
+        rgba
+    }
+
+    /// 各色のアルファ(PNGのtRNS向け)
+    pub fn alpha(&self) -> Vec<u8> {
+        self.colors.iter().map(|color| color.a).collect()
+    }
+
+    /// インデックスからRGBAを復元する
+    /// alphaはピクセルごとの透明度で、空の場合はパレットのアルファを使う
+    /// パレットにないインデックスがある場合や、alphaの長さがインデックスと違う場合はエラーにする
+    pub fn expand(&self, indices: &[u8], alpha: &[u8]) -> Result<Vec<u8>, Error> {
+        if !alpha.is_empty() && alpha.len() != indices.len() {
+            return Err(Error::InvalidInput(format!("alpha must be empty or have {} values, got {}", indices.len(), alpha.len())));
+        }
+        if let Some(index) = indices.iter().find(|&&index| index as usize >= self.colors.len()) {
+            return Err(Error::InvalidInput(format!("indices must be less than the palette length ({}), got {}", self.colors.len(), index)));
+        }
+
+        let mut image_data: Vec<u8> = Vec::with_capacity(indices.len() * 4);
+        for (i, index) in indices.iter().enumerate() {
+            let color = self.colors[*index as usize];
+            image_data.push(color.r);
+            image_data.push(color.g);
+            image_data.push(color.b);
+            image_data.push(if alpha.is_empty() { color.a } else { alpha[i] });
+        }
+        Ok(image_data)
+    }
+}
+
+impl From<Vec<Colors>> for Palette {
+    fn from(colors: Vec<Colors>) -> Self {
+        Palette { colors, transparent: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let palette = Palette::from_rgb(&[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(palette.expand(&[1, 0], &[]).unwrap(), [4, 5, 6, 255, 1, 2, 3, 255]);
+        assert_eq!(palette.expand(&[1, 0], &[10, 20]).unwrap(), [4, 5, 6, 10, 1, 2, 3, 20]);
+
+        let error = palette.expand(&[2], &[]).unwrap_err();
+        assert_eq!(error.to_string(), "invalid input: indices must be less than the palette length (2), got 2");
+        let error = palette.expand(&[0, 0], &[255]).unwrap_err();
+        assert_eq!(error.to_string(), "invalid input: alpha must be empty or have 2 values, got 1");
+    }
+
+    #[test]
+    fn test_with_alpha() {
+        let palette = Palette::from_rgb(&[1, 2, 3, 4, 5, 6]).unwrap().with_alpha(&[0, 128]).unwrap();
+        assert_eq!(palette.alpha(), [0, 128]);
+        assert_eq!(palette.expand(&[1, 0], &[]).unwrap(), [4, 5, 6, 128, 1, 2, 3, 0]);
+
+        let error = palette.with_alpha(&[255]).unwrap_err();
+        assert_eq!(error.to_string(), "invalid input: alpha must have 2 values, got 1");
+    }
+
+    #[test]
+    fn test_from_rgb() {
+        assert_eq!(Palette::from_rgba(&[1, 2, 3, 4]).unwrap().colors(), [Colors::new(1, 2, 3, 4, 0)]);
+
+        let error = Palette::from_rgb(&[10, 20, 30, 40]).unwrap_err();
+        assert_eq!(error.to_string(), "invalid input: palette must have a multiple of 3 values, got 4");
+        let error = Palette::from_rgba(&[10, 20, 30]).unwrap_err();
+        assert_eq!(error.to_string(), "invalid input: palette must have a multiple of 4 values, got 3");
+    }
+}
@@ -0,0 +1,109 @@
+use serde::Deserialize;
+
+/// 組み込みの固定パレット
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Preset {
+    /// Webセーフカラー(各チャンネル0, 51, 102, 153, 204, 255の216色)
+    WebSafe,
+    /// ゲームボーイ(DMG)の緑がかった4階調
+    GameBoy,
+    /// CGAの16色
+    Cga,
+    /// PICO-8の16色
+    Pico8,
+    /// 7色のACeP電子ペーパー
+    Acep,
+}
+
+const GAME_BOY: [[u8; 3]; 4] = [
+    [0x0f, 0x38, 0x0f],
+    [0x30, 0x62, 0x30],
+    [0x8b, 0xac, 0x0f],
+    [0x9b, 0xbc, 0x0f],
+];
+
+const CGA: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00], [0x00, 0x00, 0xaa], [0x00, 0xaa, 0x00], [0x00, 0xaa, 0xaa],
+    [0xaa, 0x00, 0x00], [0xaa, 0x00, 0xaa], [0xaa, 0x55, 0x00], [0xaa, 0xaa, 0xaa],
+    [0x55, 0x55, 0x55], [0x55, 0x55, 0xff], [0x55, 0xff, 0x55], [0x55, 0xff, 0xff],
+    [0xff, 0x55, 0x55], [0xff, 0x55, 0xff], [0xff, 0xff, 0x55], [0xff, 0xff, 0xff],
+];
+
+const PICO8: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00], [0x1d, 0x2b, 0x53], [0x7e, 0x25, 0x53], [0x00, 0x87, 0x51],
+    [0xab, 0x52, 0x36], [0x5f, 0x57, 0x4f], [0xc2, 0xc3, 0xc7], [0xff, 0xf1, 0xe8],
+    [0xff, 0x00, 0x4d], [0xff, 0xa3, 0x00], [0xff, 0xec, 0x27], [0x00, 0xe4, 0x36],
+    [0x29, 0xad, 0xff], [0x83, 0x76, 0x9c], [0xff, 0x77, 0xa8], [0xff, 0xcc, 0xaa],
+];
+
+// 黒、白、緑、青、赤、黄、オレンジ(パネルのインクの並び順)
+const ACEP: [[u8; 3]; 7] = [
+    [0x00, 0x00, 0x00],
+    [0xff, 0xff, 0xff],
+    [0x00, 0xff, 0x00],
+    [0x00, 0x00, 0xff],
+    [0xff, 0x00, 0x00],
+    [0xff, 0xff, 0x00],
+    [0xff, 0x80, 0x00],
+];
+
+impl Preset {
+    /// パレットの色 [r, g, b] の一覧
+    pub fn colors(&self) -> Vec<[u8; 3]> {
+        match self {
+            Preset::WebSafe => {
+                let mut colors: Vec<[u8; 3]> = Vec::with_capacity(216);
+                for r in 0..6 {
+                    for g in 0..6 {
+                        for b in 0..6 {
+                            colors.push([r * 51, g * 51, b * 51]);
+                        }
+                    }
+                }
+                colors
+            }
+            Preset::GameBoy => GAME_BOY.to_vec(),
+            Preset::Cga => CGA.to_vec(),
+            Preset::Pico8 => PICO8.to_vec(),
+            Preset::Acep => ACEP.to_vec(),
+        }
+    }
+}
+
+/// 減色せずにそのまま使うパレット(組み込みのパレットの名前か、[[r, g, b], ...])
+#[derive(Deserialize, PartialEq, Eq, Clone, Debug)]
+#[serde(untagged)]
+pub enum FixedPalette {
+    Preset(Preset),
+    Colors(Vec<[u8; 3]>),
+}
+
+impl FixedPalette {
+    /// パレットの色 [r, g, b] の一覧
+    pub fn colors(&self) -> Vec<[u8; 3]> {
+        match self {
+            FixedPalette::Preset(preset) => preset.colors(),
+            FixedPalette::Colors(colors) => colors.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colors() {
+        let web_safe = Preset::WebSafe.colors();
+        assert_eq!(web_safe.len(), 216);
+        assert_eq!(web_safe[1], [0, 0, 51]);
+        assert_eq!(web_safe[215], [255, 255, 255]);
+        assert_eq!(Preset::GameBoy.colors().len(), 4);
+        assert_eq!(Preset::Cga.colors().len(), 16);
+        assert_eq!(Preset::Pico8.colors().len(), 16);
+        assert_eq!(Preset::Acep.colors().len(), 7);
+
+        assert_eq!(FixedPalette::Colors(vec![[1, 2, 3]]).colors(), [[1, 2, 3]]);
+    }
+}
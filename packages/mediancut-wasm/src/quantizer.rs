@@ -0,0 +1,602 @@
+use std::collections::HashMap;
+use crate::histogram::{color_key, Colors, Histogram, Sampling};
+use crate::mediancut::{fact, get_total_and_greatest_range_channel, samples, Bucket, BucketSnapshot, Point};
+use crate::dither;
+use crate::kmeans;
+use crate::nearest::Nearest;
+use crate::octree::Octree;
+use crate::options::{Algorithm, ColorSpace, Dither, Options};
+use crate::error::Error;
+use crate::palette::Palette;
+use crate::wu;
+
+/// パレットを作って減色する(パレットの作り方はOptions::algorithmで選ぶ)
+#[derive(PartialEq, Clone, Debug)]
+pub struct Quantizer {
+    options: Options,
+}
+
+/// 減色の結果(パレットと、元の色からパレットのインデックスへの対応表)
+/// 対応表にない色は最も近いパレットの色にする
+#[derive(Clone, Debug)]
+pub struct Quantization {
+    palette: Palette,
+    palette_map: HashMap<u32, usize>,
+    sampling: Sampling,
+    nearest: Nearest,
+}
+
+impl Quantizer {
+    /// sizeは減色後の最大の色数
+    pub fn new(size: u8) -> Quantizer {
+        Quantizer { options: Options { size, ..Options::default() } }
+    }
+
+    pub fn from_options(options: Options) -> Result<Quantizer, Error> {
+        options.validate()?;
+        Ok(Quantizer { options })
+    }
+
+    /// 各チャンネルの有効ビット数(5〜8)を指定する
+    pub fn with_precision(mut self, precision: u8) -> Result<Quantizer, Error> {
+        self.options.precision = precision;
+        self.options.validate()?;
+        Ok(self)
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// オプションに合わせてヒストグラムを作る
+    pub fn histogram(&self, data: &[u8]) -> Histogram {
+        Histogram::with_sampling(data, Sampling::new(&self.options))
+    }
+
+    pub fn quantize(&self, histogram: &Histogram) -> Quantization {
+        self.quantize_with_steps(histogram, &mut Vec::new())
+    }
+
+    /// 分割の過程(1回分割するごとのbucketの一覧)を返す
+    pub fn steps(&self, histogram: &Histogram) -> Vec<Vec<BucketSnapshot>> {
+        let mut steps: Vec<Vec<BucketSnapshot>> = Vec::new();
+        self.quantize_with_steps(histogram, &mut steps);
+        steps
+    }
+
+    /// 減色の結果と分割の過程を1回の減色でまとめて返す
+    pub fn quantize_and_steps(&self, histogram: &Histogram) -> (Quantization, Vec<Vec<BucketSnapshot>>) {
+        let mut steps: Vec<Vec<BucketSnapshot>> = Vec::new();
+        let quantization = self.quantize_with_steps(histogram, &mut steps);
+        (quantization, steps)
+    }
+
+    /// RGBAのピクセル列を減色したRGBAを返す
+    /// widthは画像の幅(ディザリングで使う)
+    /// alphaオプションが無効な場合、アルファはそのまま
+    pub fn reduce(&self, data: &[u8], width: usize) -> Result<Vec<u8>, Error> {
+        let quantization = self.quantize(&self.histogram(data));
+        let indices = quantization.dithered_indices(data, width, &self.options)?;
+        Ok(quantization.expand(data, &indices))
+    }
+
+    /// RGBAのピクセル列を減色し、パレットとピクセルごとのインデックスを返す
+    pub fn reduce_indexed(&self, data: &[u8], width: usize) -> Result<(Palette, Vec<u8>), Error> {
+        let quantization = self.quantize(&self.histogram(data));
+        let indices = quantization.dithered_indices(data, width, &self.options)?;
+        Ok((quantization.palette, indices))
+    }
+
+    /// 別の画像などで作ったパレットの色に置き換えたRGBAを返す
+    pub fn remap(&self, data: &[u8], width: usize, palette: &Palette) -> Result<Vec<u8>, Error> {
+        let quantization = Quantization::from_palette(palette.clone(), &self.options)?;
+        let indices = quantization.dithered_indices(data, width, &self.options)?;
+        Ok(quantization.expand(data, &indices))
+    }
+
+    fn quantize_with_steps(&self, histogram: &Histogram, steps: &mut Vec<Vec<BucketSnapshot>>) -> Quantization {
+        // 固定のパレットを使う場合は分割しない
+        // 色数はOptions::validateで確認済み
+        if let Some(palette) = &self.options.palette {
+            return Palette::from_rgb(&palette.colors().concat()).and_then(|palette| Quantization::from_palette(palette, &self.options)).unwrap();
+        }
+
+        // 透明なピクセルを除外する場合は、透明用に1色確保しておく
+        // (このヒストグラムに透明なピクセルがなくても、同じ結果で別の画像を減色できるように確保する)
+        let mut options = self.options.clone();
+        if options.transparent {
+            options.size = options.size.saturating_sub(1);
+        }
+
+        let (mut quantization, locked) = match options.algorithm {
+            Algorithm::MedianCut => self.median_cut(histogram, &options, steps),
+            Algorithm::Octree => (Quantization::from_octree(histogram, &options), 0),
+            Algorithm::Wu => (Quantization::from_wu(histogram, &options), 0),
+        };
+        if options.kmeans_iterations > 0 {
+            quantization.refine(histogram.colors(), locked, &options);
+        }
+        if options.transparent {
+            quantization.palette.push_transparent(histogram.transparent());
+        }
+        quantization
+    }
+
+    // メディアンカットで減色し、固定する色の数と一緒に返す
+    fn median_cut(&self, histogram: &Histogram, options: &Options, steps: &mut Vec<Vec<BucketSnapshot>>) -> (Quantization, usize) {
+        let mut options = options.clone();
+
+        // 固定する色は先にパレットに入れておき、同じ色はbucketの分割から除外する
+        let locked: Vec<Colors> = options.locked_colors.iter().map(|c| Colors::new(c[0], c[1], c[2], 255, 0)).collect();
+        options.size = options.size.saturating_sub(locked.len() as u8);
+        // ヒストグラムと同じ方法で固定する色を変換して比べる
+        let sampling = histogram.sampling();
+        let sampled: Vec<(u8, u8, u8, u8)> = locked.iter().filter_map(|l| sampling.color(&[l.r, l.g, l.b, l.a])).collect();
+        let is_locked = |c: &Colors| {
+            sampled.iter().any(|&(r, g, b, a)| c.r == r && c.g == g && c.b == b && (!sampling.alpha() || c.a == a))
+        };
+        let (excluded, colors): (Vec<Colors>, Vec<Colors>) = histogram.colors().iter()
+            .partition(|c| !locked.is_empty() && (options.size == 0 || is_locked(c)));
+
+        // sRGBの場合は座標に変換せずにヒストグラムの色のまま分割する
+        let quantization = match options.color_space {
+            ColorSpace::Srgb => Quantization::from_median_cut(colors, &locked, excluded, &options, sampling, steps),
+            _ => Quantization::from_median_cut(samples(&colors, &options), &locked, excluded, &options, sampling, steps),
+        };
+        (quantization, locked.len())
+    }
+}
+
+
+fn coords(c: &Colors) -> [f32; 4] {
+    [c.r as f32, c.g as f32, c.b as f32, c.a as f32]
+}
+
+impl Quantization {
+    // メディアンカットで分割し、固定する色とbucketごとの平均色をパレットにする
+    fn from_median_cut<P: Point>(points: Vec<P>, locked: &[Colors], mut excluded: Vec<Colors>, options: &Options, sampling: Sampling, steps: &mut Vec<Vec<BucketSnapshot>>) -> Quantization {
+        // 再帰的に分割をしていく（lengthがcolorSizeになるまで）
+        let mut buckets = if points.is_empty() && !excluded.is_empty() {
+            Vec::new()
+        } else {
+            let bucket = get_total_and_greatest_range_channel(points, options);
+            fact(vec![bucket], options, steps)
+        };
+
+        // 平均色より固定する色の方が近い色は固定する色に置き換え、残りの色で平均色を求め直す
+        if !locked.is_empty() {
+            let locked_nearest = Nearest::new(&Palette::new(locked.to_vec()), sampling.alpha());
+            for bucket in buckets.iter_mut() {
+                let average = coords(&Colors::new(bucket.average.0, bucket.average.1, bucket.average.2, bucket.average.3, 0));
+                let (moved, kept): (Vec<P>, Vec<P>) = bucket.colors.iter().partition(|point| {
+                    let color = coords(point.color());
+                    locked_nearest.find_with_distance(color).1 <= Nearest::distance(average, color, sampling.alpha())
+                });
+                if !moved.is_empty() {
+                    excluded.extend(moved.iter().map(|point| *point.color()));
+                    *bucket = get_total_and_greatest_range_channel(kept, options);
+                }
+            }
+        }
+
+        // 平均色を求める
+        Quantization::from_buckets(&buckets, locked, &excluded, sampling)
+    }
+
+    // 固定する色とbucketごとの平均色をパレットとして並べ、元の色からパレットへの対応表を作る
+    // 分割から除外した色は最も近い固定する色に置き換える
+    fn from_buckets<P: Point>(buckets: &[Bucket<P>], locked: &[Colors], excluded: &[Colors], sampling: Sampling) -> Quantization {
+        let mut palette: Vec<Colors> = locked.to_vec();
+        let mut palette_map: HashMap<u32, usize> = HashMap::new();
+        let locked_nearest = Nearest::new(&Palette::new(locked.to_vec()), sampling.alpha());
+        for color in excluded {
+            let index = locked_nearest.find(coords(color));
+            palette[index].count += color.count;
+            palette_map.insert(color_key(color.r, color.g, color.b, color.a), index);
+        }
+
+        for bucket in buckets {
+            if bucket.total == 0 {
+                continue;
+            }
+            let (r, g, b, a) = bucket.average;
+            for color in bucket.colors.iter().map(Point::color) {
+                palette_map.insert(color_key(color.r, color.g, color.b, color.a), palette.len());
+            }
+            palette.push(Colors::new(r, g, b, a, bucket.total));
+        }
+        let palette = Palette::new(palette);
+        let nearest = Nearest::new(&palette, sampling.alpha());
+        Quantization { palette, palette_map, sampling, nearest }
+    }
+
+    // 八分木の葉ごとの平均色をパレットとして並べ、元の色からパレットへの対応表を作る
+    fn from_octree(histogram: &Histogram, options: &Options) -> Quantization {
+        let mut octree = Octree::new(options.size as usize, options.alpha);
+        for color in histogram.colors() {
+            octree.insert(color);
+        }
+        let palette = Palette::new(octree.palette());
+        let palette_map: HashMap<u32, usize> = histogram.colors().iter()
+            .map(|color| (color_key(color.r, color.g, color.b, color.a), octree.index(color)))
+            .collect();
+        let sampling = histogram.sampling();
+        let nearest = Nearest::new(&palette, sampling.alpha());
+        Quantization { palette, palette_map, sampling, nearest }
+    }
+
+    // Wuの方法で分割した箱ごとの平均色をパレットとして並べ、元の色からパレットへの対応表を作る
+    fn from_wu(histogram: &Histogram, options: &Options) -> Quantization {
+        let (palette, assignments) = wu::quantize(histogram.colors(), options.size as usize);
+        let palette = Palette::new(palette);
+        let palette_map: HashMap<u32, usize> = histogram.colors().iter().zip(assignments)
+            .map(|(color, index)| (color_key(color.r, color.g, color.b, color.a), index))
+            .collect();
+        let sampling = histogram.sampling();
+        let nearest = Nearest::new(&palette, sampling.alpha());
+        Quantization { palette, palette_map, sampling, nearest }
+    }
+
+    // パレットをk-meansで調整し、元の色からパレットへの対応表を作り直す
+    // 先頭からfixed個の色(固定する色)は動かさない
+    fn refine(&mut self, colors: &[Colors], fixed: usize, options: &Options) {
+        let (palette, assignments) = kmeans::refine(colors, self.palette.colors(), fixed, options);
+        self.palette_map = colors.iter().zip(assignments).map(|(color, index)| (color_key(color.r, color.g, color.b, color.a), index)).collect();
+        self.palette = Palette::new(palette);
+        self.nearest = Nearest::new(&self.palette, options.alpha);
+    }
+
+    /// 指定したパレットの色に、最も近い色で置き換えるようにする
+    /// 透明なピクセルを除外する場合は、透明用の色をパレットの末尾に追加する
+    /// インデックスをu8で表せるように、透明用の色を含めて1〜256色でない場合や、オプションが正しくない場合はエラーにする
+    pub fn from_palette(mut palette: Palette, options: &Options) -> Result<Quantization, Error> {
+        options.validate()?;
+        if options.transparent && palette.transparent_index().is_none() {
+            palette.push_transparent(0);
+        }
+        let opaque = palette.len() - palette.transparent_index().map_or(0, |_| 1);
+        if opaque == 0 || palette.len() > 256 {
+            let max = 256 - (palette.len() - opaque);
+            return Err(Error::InvalidInput(format!("palette must have between 1 and {} colors, got {}", max, opaque)));
+        }
+        let nearest = Nearest::new(&palette, options.alpha);
+        Ok(Quantization { palette, palette_map: HashMap::new(), sampling: Sampling::new(options), nearest })
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    // ピクセルに対応するパレットのインデックス
+    fn index(&self, pixel: &[u8]) -> usize {
+        let Some(key) = self.sampling.key(pixel) else {
+            return self.palette.transparent_index().unwrap();
+        };
+        match self.palette_map.get(&key) {
+            Some(index) => *index,
+            None => {
+                let (r, g, b, a) = self.sampling.exact_color(pixel).unwrap();
+                self.nearest.find([r as f32, g as f32, b as f32, a as f32])
+            }
+        }
+    }
+
+    /// 各ピクセルをパレットのインデックスに置き換える
+    /// ヒストグラムに含まれていない色は最も近いパレットの色にする
+    pub fn indices(&self, data: &[u8]) -> Vec<u8> {
+        data.chunks_exact(4).map(|pixel| self.index(pixel) as u8).collect()
+    }
+
+    /// ディザリングのオプションに合わせて、各ピクセルをパレットのインデックスに置き換える
+    /// widthが0の場合や、ピクセル数を割り切れない場合はエラーにする
+    pub fn dithered_indices(&self, data: &[u8], width: usize, options: &Options) -> Result<Vec<u8>, Error> {
+        let pixels = data.len() / 4;
+        if width == 0 || pixels % width != 0 {
+            return Err(Error::InvalidInput(format!("width must be a positive divisor of the number of pixels ({}), got {}", pixels, width)));
+        }
+        let indices = match options.dither {
+            Dither::None => self.indices(data),
+            Dither::Bayer => to_u8(dither::ordered(data, width, &self.palette, &self.sampling, options)),
+            Dither::BlueNoise => to_u8(dither::blue_noise(data, width, &self.palette, &self.sampling, options)),
+            Dither::Pattern => to_u8(dither::pattern(data, width, &self.palette, &self.sampling, options)),
+            _ => to_u8(dither::diffuse(data, width, &self.palette, &self.sampling, options)),
+        };
+        Ok(indices)
+    }
+
+    /// 平均色を元に色を置き換えていく
+    /// アルファを考慮しない場合、アルファはそのまま
+    pub fn reduce(&self, data: &[u8]) -> Vec<u8> {
+        self.expand(data, &self.indices(data))
+    }
+
+    // インデックスの色でピクセルを置き換える
+    fn expand(&self, data: &[u8], indices: &[u8]) -> Vec<u8> {
+        let palette = self.palette.colors();
+        let transparent = self.palette.transparent_index();
+        let mut image_data: Vec<u8> = Vec::with_capacity(data.len());
+        for (pixel, &index) in data.chunks_exact(4).zip(indices) {
+            let index = index as usize;
+            let color = palette[index];
+            image_data.push(color.r);
+            image_data.push(color.g);
+            image_data.push(color.b);
+            if transparent == Some(index) {
+                image_data.push(0);
+            } else {
+                image_data.push(self.sampling.output_alpha(pixel, &color));
+            }
+        }
+        image_data
+    }
+}
+
+fn to_u8(indices: Vec<usize>) -> Vec<u8> {
+    indices.into_iter().map(|index| index as u8).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::CutPosition;
+    use crate::presets::{FixedPalette, Preset};
+
+    #[test]
+    fn test_quantize() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255];
+        let quantization = Quantizer::new(2).quantize(&Histogram::from_rgba(&data));
+        assert_eq!(quantization.palette().colors(), [
+            Colors::new(170, 0, 85, 255, 3),
+            Colors::new(0, 255, 0, 255, 1),
+        ]);
+        assert_eq!(quantization.indices(&data), [0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_reduce_indexed() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 255, 0, 0, 128, 0, 255, 0, 255, 0, 0, 255, 255];
+        let quantizer = Quantizer::new(2);
+        let (palette, indices) = quantizer.reduce_indexed(&data, 4).unwrap();
+        assert_eq!(palette.to_rgb(), [170, 0, 85, 0, 255, 0]);
+        assert_eq!(indices, [0, 0, 1, 0]);
+
+        let alpha: Vec<u8> = data.iter().skip(3).step_by(4).copied().collect();
+        assert_eq!(palette.expand(&indices, &alpha).unwrap(), quantizer.reduce(&data, 4).unwrap());
+    }
+
+    #[test]
+    fn test_reduce_with_alpha() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 250, 0, 0, 250, 0, 0, 255, 10, 0, 0, 250, 0];
+        let options = Options { size: 2, alpha: true, ..Options::default() };
+        let quantizer = Quantizer::from_options(options).unwrap();
+        let (palette, indices) = quantizer.reduce_indexed(&data, 4).unwrap();
+        assert_eq!(palette.colors(), [
+            Colors::new(0, 0, 255, 5, 2),
+            Colors::new(253, 0, 0, 253, 2),
+        ]);
+        assert_eq!(indices, [1, 1, 0, 0]);
+        assert_eq!(palette.alpha(), [5, 253]);
+        assert_eq!(quantizer.reduce(&data, 4).unwrap(), palette.expand(&indices, &[]).unwrap());
+    }
+
+    #[test]
+    fn test_reduce_with_transparent() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 0, 255, 0, 0, 0, 0, 255, 255, 9, 9, 9, 0];
+        let options = Options { size: 2, transparent: true, ..Options::default() };
+        let quantizer = Quantizer::from_options(options).unwrap();
+        let (palette, indices) = quantizer.reduce_indexed(&data, 4).unwrap();
+        assert_eq!(palette.colors(), [
+            Colors::new(128, 0, 128, 255, 2),
+            Colors::new(0, 0, 0, 0, 2),
+        ]);
+        assert_eq!(palette.transparent_index(), Some(1));
+        assert_eq!(indices, [0, 1, 0, 1]);
+        assert_eq!(quantizer.reduce(&data, 4).unwrap(), [
+            128, 0, 128, 255, 0, 0, 0, 0, 128, 0, 128, 255, 0, 0, 0, 0,
+        ]);
+    }
+
+    #[test]
+    fn test_reduce_all_transparent() {
+        // 透明なピクセルを除くと色がなくなる場合も分割しようとしない
+        let data: Vec<u8> = vec![1, 2, 3, 0, 4, 5, 6, 0];
+        let options = Options { transparent: true, cut_position: CutPosition::Population, ..Options::default() };
+        let quantizer = Quantizer::from_options(options).unwrap();
+        assert_eq!(quantizer.reduce(&data, 2).unwrap(), [0, 0, 0, 0, 0, 0, 0, 0]);
+        let options = Options { transparent: true, cut_position: CutPosition::Optimal, ..Options::default() };
+        assert_eq!(Quantizer::from_options(options).unwrap().reduce(&data, 2).unwrap(), [0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(Quantizer::new(2).quantize(&Histogram::from_rgba(&[])).palette().len(), 0);
+    }
+
+    #[test]
+    fn test_reduce_with_invalid_width() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255];
+        let quantizer = Quantizer::new(2);
+        assert_eq!(quantizer.reduce(&data, 0).unwrap_err().to_string(), "invalid input: width must be a positive divisor of the number of pixels (3), got 0");
+        assert!(quantizer.reduce_indexed(&data, 2).is_err());
+        assert!(quantizer.remap(&data, 2, &Palette::from_rgb(&[0, 0, 0]).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_reduce_with_matte() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 255, 0, 0, 0];
+        let options = Options { matte: Some([0, 0, 255]), ..Options::default() };
+        let quantizer = Quantizer::from_options(options).unwrap();
+        assert_eq!(quantizer.reduce(&data, 2).unwrap(), [255, 0, 0, 255, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_reduce_other_image() {
+        // ヒストグラムにない色は最も近いパレットの色になる
+        let data: Vec<u8> = vec![255, 0, 0, 255, 0, 255, 0, 255];
+        let quantization = Quantizer::new(2).quantize(&Histogram::from_rgba(&data));
+        let other: Vec<u8> = vec![0, 200, 50, 255, 250, 10, 0, 255];
+        assert_eq!(quantization.indices(&other), [1, 0]);
+        assert_eq!(quantization.reduce(&other), [0, 255, 0, 255, 255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_remap() {
+        let palette = Palette::from_rgb(&[0, 0, 0, 255, 255, 255]).unwrap();
+        let data: Vec<u8> = vec![30, 30, 30, 255, 220, 220, 220, 255, 0, 0, 0, 0];
+        let options = Options { transparent: true, ..Options::default() };
+        let quantizer = Quantizer::from_options(options).unwrap();
+        assert_eq!(quantizer.remap(&data, 3, &palette).unwrap(), [0, 0, 0, 255, 255, 255, 255, 255, 0, 0, 0, 0]);
+
+        let quantization = Quantization::from_palette(palette, quantizer.options()).unwrap();
+        assert_eq!(quantization.palette().transparent_index(), Some(2));
+        assert_eq!(quantization.indices(&data), [0, 1, 2]);
+
+        assert!(Quantization::from_palette(Palette::from_rgb(&[0, 0, 0]).unwrap(), &Options { precision: 9, ..Options::default() }).is_err());
+        let error = Quantization::from_palette(Palette::from_rgb(&[]).unwrap(), &Options::default()).unwrap_err();
+        assert_eq!(error.to_string(), "invalid input: palette must have between 1 and 256 colors, got 0");
+        let error = Quantization::from_palette(Palette::from_rgb(&[0; 256 * 3]).unwrap(), quantizer.options()).unwrap_err();
+        assert_eq!(error.to_string(), "invalid input: palette must have between 1 and 255 colors, got 256");
+    }
+
+    #[test]
+    fn test_reduce_other_image_with_transparent() {
+        // 1枚目に透明なピクセルがなくても、透明用の色を確保しておく
+        let options = Options { size: 2, transparent: true, ..Options::default() };
+        let quantizer = Quantizer::from_options(options).unwrap();
+        let quantization = quantizer.quantize(&quantizer.histogram(&[255, 0, 0, 255]));
+        assert_eq!(quantization.palette().colors(), [Colors::new(255, 0, 0, 255, 1), Colors::new(0, 0, 0, 0, 0)]);
+        assert_eq!(quantization.palette().transparent_index(), Some(1));
+        assert_eq!(quantization.reduce(&[0, 0, 0, 0, 250, 0, 0, 255]), [0, 0, 0, 0, 255, 0, 0, 255]);
+
+        let options = Options { dither: Dither::FloydSteinberg, ..quantizer.options().clone() };
+        let quantization = Quantizer::from_options(options.clone()).unwrap().quantize(&quantizer.histogram(&[255, 0, 0, 255]));
+        assert_eq!(quantization.dithered_indices(&[0, 0, 0, 0, 250, 0, 0, 255], 2, &options).unwrap(), [1, 0]);
+    }
+
+    #[test]
+    fn test_fixed_palette() {
+        let data: Vec<u8> = vec![20, 60, 20, 255, 150, 180, 20, 255, 0, 0, 0, 0];
+        let options = Options { palette: Some(FixedPalette::Preset(Preset::GameBoy)), transparent: true, ..Options::default() };
+        let quantizer = Quantizer::from_options(options).unwrap();
+        let (palette, indices) = quantizer.reduce_indexed(&data, 3).unwrap();
+        assert_eq!(palette.len(), 5);
+        assert_eq!(palette.to_rgb()[0..3], [0x0f, 0x38, 0x0f]);
+        assert_eq!(indices, [0, 3, 4]);
+        assert!(quantizer.steps(&quantizer.histogram(&data)).is_empty());
+
+        let options = Options { palette: Some(FixedPalette::Colors(vec![[0, 0, 0], [255, 255, 255]])), dither: Dither::FloydSteinberg, ..Options::default() };
+        let quantizer = Quantizer::from_options(options).unwrap();
+        let data: Vec<u8> = [128, 128, 128, 255].repeat(4);
+        assert_eq!(quantizer.reduce_indexed(&data, 2).unwrap().1, [1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_locked_colors() {
+        let data: Vec<u8> = vec![250, 250, 250, 255, 255, 0, 0, 255, 0, 0, 255, 255, 10, 10, 10, 255];
+        let options = Options { size: 3, locked_colors: vec![[255, 255, 255], [0, 0, 0]], ..Options::default() };
+        let quantizer = Quantizer::from_options(options).unwrap();
+        let (palette, indices) = quantizer.reduce_indexed(&data, 4).unwrap();
+        // 固定した色が先頭に入り、残りの1色をメディアンカットで決める
+        assert_eq!(palette.colors(), [
+            Colors::new(255, 255, 255, 255, 1),
+            Colors::new(0, 0, 0, 255, 1),
+            Colors::new(128, 0, 128, 255, 2),
+        ]);
+        assert_eq!(indices, [0, 2, 2, 1]);
+
+        // 全ての枠を固定した場合は最も近い固定した色になる
+        let options = Options { size: 2, locked_colors: vec![[255, 255, 255], [0, 0, 0]], ..Options::default() };
+        let quantizer = Quantizer::from_options(options).unwrap();
+        let (palette, indices) = quantizer.reduce_indexed(&data, 4).unwrap();
+        assert_eq!(palette.len(), 2);
+        assert_eq!(indices, [0, 1, 1, 1]);
+
+        // 全ての色が固定した色の方に近い場合は、メディアンカットの色を追加しない
+        let data: Vec<u8> = vec![250, 250, 250, 255, 10, 10, 10, 255];
+        let options = Options { size: 3, locked_colors: vec![[255, 255, 255], [0, 0, 0]], ..Options::default() };
+        let (palette, indices) = Quantizer::from_options(options).unwrap().reduce_indexed(&data, 2).unwrap();
+        assert_eq!(palette.len(), 2);
+        assert_eq!(indices, [0, 1]);
+
+        // 下位ビットを無視する場合も、固定した色と同じ色は分割から除外する
+        let data: Vec<u8> = [0, 0, 0, 0, 0, 0, 255, 255].iter().flat_map(|&v| [v, v, v, 255]).collect();
+        let options = Options { size: 3, precision: 5, locked_colors: vec![[0, 0, 0]], ..Options::default() };
+        let (palette, indices) = Quantizer::from_options(options).unwrap().reduce_indexed(&data, 8).unwrap();
+        assert_eq!(palette.colors(), [Colors::new(0, 0, 0, 255, 6), Colors::new(248, 248, 248, 255, 2)]);
+        assert_eq!(indices, [0, 0, 0, 0, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_kmeans() {
+        let data: Vec<u8> = [0, 1, 2, 3, 100, 110].iter().flat_map(|&r| [r, 0, 0, 255]).collect();
+        let quantizer = Quantizer::new(2);
+        let (palette, indices) = quantizer.reduce_indexed(&data, 6).unwrap();
+        assert_eq!(palette.colors(), [Colors::new(1, 0, 0, 255, 3), Colors::new(71, 0, 0, 255, 3)]);
+        assert_eq!(indices, [0, 0, 0, 1, 1, 1]);
+
+        // 3は71より1に近いので、k-meansで調整すると左の塊に入る
+        let options = Options { size: 2, kmeans_iterations: 10, ..Options::default() };
+        let quantizer = Quantizer::from_options(options).unwrap();
+        let (palette, indices) = quantizer.reduce_indexed(&data, 6).unwrap();
+        assert_eq!(palette.colors(), [Colors::new(2, 0, 0, 255, 4), Colors::new(105, 0, 0, 255, 2)]);
+        assert_eq!(indices, [0, 0, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_octree() {
+        // 8つの象限の全てに色がある画像でも、sizeが8未満の場合にsize色になる
+        let mut corners: Vec<u8> = Vec::new();
+        for i in 0..8 {
+            let value = |bit: u32| if i & bit == 0 { 0 } else { 255 };
+            corners.extend([value(4), value(2), value(1), 255]);
+        }
+        corners.extend([0, 0, 0, 0]);
+        for size in [2, 4, 7] {
+            let options = Options { size, algorithm: Algorithm::Octree, transparent: true, ..Options::default() };
+            let quantizer = Quantizer::from_options(options).unwrap();
+            let (palette, indices) = quantizer.reduce_indexed(&corners, 9).unwrap();
+            assert_eq!(palette.len(), size as usize);
+            assert_eq!(palette.transparent_index(), Some(size as usize - 1));
+            assert_eq!(indices[8], size - 1);
+        }
+
+        let data: Vec<u8> = vec![255, 0, 0, 255, 250, 0, 0, 255, 0, 0, 255, 255, 0, 0, 0, 0];
+        let options = Options { size: 3, algorithm: Algorithm::Octree, ..Options::default() };
+        let quantizer = Quantizer::from_options(options).unwrap();
+        let (palette, indices) = quantizer.reduce_indexed(&data, 4).unwrap();
+        assert_eq!(palette.colors(), [Colors::new(0, 0, 0, 255, 1), Colors::new(0, 0, 255, 255, 1), Colors::new(253, 0, 0, 255, 2)]);
+        assert_eq!(indices, [2, 2, 1, 0]);
+        assert_eq!(quantizer.reduce(&data, 4).unwrap(), palette.expand(&indices, &[255, 255, 255, 0]).unwrap());
+    }
+
+    #[test]
+    fn test_wu() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 250, 0, 0, 255, 0, 0, 255, 255, 0, 0, 0, 0];
+        // RGBが同じでアルファだけが違う色を分けられないので、alphaは使えない
+        let options = Options { size: 2, algorithm: Algorithm::Wu, alpha: true, ..Options::default() };
+        assert!(Quantizer::from_options(options).is_err());
+
+        // 半透明のピクセルは元のアルファのまま出力する
+        let options = Options { size: 2, algorithm: Algorithm::Wu, ..Options::default() };
+        let quantizer = Quantizer::from_options(options).unwrap();
+        let translucent: Vec<u8> = vec![255, 0, 0, 255, 255, 0, 0, 10, 0, 0, 255, 255];
+        assert_eq!(quantizer.reduce(&translucent, 3).unwrap(), [255, 0, 0, 255, 255, 0, 0, 10, 0, 0, 255, 255]);
+
+        let options = Options { size: 3, algorithm: Algorithm::Wu, ..Options::default() };
+        let quantizer = Quantizer::from_options(options).unwrap();
+        let (palette, indices) = quantizer.reduce_indexed(&data, 4).unwrap();
+        assert_eq!(palette.colors().len(), 3);
+        assert_eq!(indices.iter().map(|&index| palette.colors()[index as usize]).collect::<Vec<Colors>>(), [
+            Colors::new(253, 0, 0, 255, 2), Colors::new(253, 0, 0, 255, 2), Colors::new(0, 0, 255, 255, 1), Colors::new(0, 0, 0, 255, 1),
+        ]);
+        assert_eq!(quantizer.reduce(&data, 4).unwrap(), palette.expand(&indices, &[255, 255, 255, 0]).unwrap());
+    }
+
+    #[test]
+    fn test_reduce_with_precision() {
+        let data: Vec<u8> = vec![255, 0, 0, 255, 250, 7, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255];
+        let quantizer = Quantizer::new(4).with_precision(5).unwrap();
+        assert_eq!(quantizer.reduce(&data, 4).unwrap(), [
+            248, 0, 0, 255, 248, 0, 0, 255, 0, 248, 0, 255, 0, 0, 248, 255,
+        ]);
+
+        let error = Quantizer::new(4).with_precision(4).unwrap_err();
+        assert_eq!(error, Error::InvalidOptions("precision must be between 5 and 8, got 4".to_string()));
+    }
+}
@@ -0,0 +1,269 @@
+use crate::histogram::Colors;
+
+// Xiaolin Wuの減色(各チャンネル上位5ビットの33x33x33の累積モーメントで、分散が最小になるように箱を分割する)
+// 0番目はモーメントの計算のために空けておく
+const SIDE: usize = 33;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Cube {
+    r0: usize,
+    r1: usize,
+    g0: usize,
+    g1: usize,
+    b0: usize,
+    b1: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Direction {
+    R,
+    G,
+    B,
+}
+
+// 累積モーメント(ピクセル数、各チャンネルの合計、二乗の合計)
+struct Moments {
+    weight: Vec<i64>,
+    r: Vec<i64>,
+    g: Vec<i64>,
+    b: Vec<i64>,
+    a: Vec<i64>,
+    square: Vec<f64>,
+}
+
+fn index(r: usize, g: usize, b: usize) -> usize {
+    r * SIDE * SIDE + g * SIDE + b
+}
+
+// 色が含まれるセル
+fn cell(color: &Colors) -> (usize, usize, usize) {
+    ((color.r >> 3) as usize + 1, (color.g >> 3) as usize + 1, (color.b >> 3) as usize + 1)
+}
+
+impl Moments {
+    fn new(colors: &[Colors]) -> Moments {
+        let length = SIDE * SIDE * SIDE;
+        let mut moments = Moments {
+            weight: vec![0; length],
+            r: vec![0; length],
+            g: vec![0; length],
+            b: vec![0; length],
+            a: vec![0; length],
+            square: vec![0.0; length],
+        };
+        for color in colors {
+            let (r, g, b) = cell(color);
+            let i = index(r, g, b);
+            let count = color.count as i64;
+            moments.weight[i] += count;
+            moments.r[i] += color.r as i64 * count;
+            moments.g[i] += color.g as i64 * count;
+            moments.b[i] += color.b as i64 * count;
+            moments.a[i] += color.a as i64 * count;
+            moments.square[i] += (color.r as f64).powi(2) * count as f64 + (color.g as f64).powi(2) * count as f64 + (color.b as f64).powi(2) * count as f64;
+        }
+
+        // 原点からの累積にする
+        for table in [&mut moments.weight, &mut moments.r, &mut moments.g, &mut moments.b, &mut moments.a] {
+            accumulate(table);
+        }
+        accumulate(&mut moments.square);
+        moments
+    }
+}
+
+fn accumulate<T: Copy + Default + std::ops::AddAssign + std::ops::Add<Output = T>>(table: &mut [T]) {
+    for r in 1..SIDE {
+        let mut area = [T::default(); SIDE];
+        for g in 1..SIDE {
+            let mut line = T::default();
+            for b in 1..SIDE {
+                line += table[index(r, g, b)];
+                area[b] += line;
+                table[index(r, g, b)] = table[index(r - 1, g, b)] + area[b];
+            }
+        }
+    }
+}
+
+// 箱の中の合計
+fn volume<T: Copy + std::ops::Add<Output = T> + std::ops::Sub<Output = T>>(cube: &Cube, m: &[T]) -> T {
+    m[index(cube.r1, cube.g1, cube.b1)] - m[index(cube.r1, cube.g1, cube.b0)] - m[index(cube.r1, cube.g0, cube.b1)] + m[index(cube.r1, cube.g0, cube.b0)]
+        - m[index(cube.r0, cube.g1, cube.b1)] + m[index(cube.r0, cube.g1, cube.b0)] + m[index(cube.r0, cube.g0, cube.b1)] - m[index(cube.r0, cube.g0, cube.b0)]
+}
+
+// 箱の下端の面より手前の部分(分割位置によらない項)
+fn bottom(cube: &Cube, direction: Direction, m: &[i64]) -> i64 {
+    match direction {
+        Direction::R => -m[index(cube.r0, cube.g1, cube.b1)] + m[index(cube.r0, cube.g1, cube.b0)] + m[index(cube.r0, cube.g0, cube.b1)] - m[index(cube.r0, cube.g0, cube.b0)],
+        Direction::G => -m[index(cube.r1, cube.g0, cube.b1)] + m[index(cube.r1, cube.g0, cube.b0)] + m[index(cube.r0, cube.g0, cube.b1)] - m[index(cube.r0, cube.g0, cube.b0)],
+        Direction::B => -m[index(cube.r1, cube.g1, cube.b0)] + m[index(cube.r1, cube.g0, cube.b0)] + m[index(cube.r0, cube.g1, cube.b0)] - m[index(cube.r0, cube.g0, cube.b0)],
+    }
+}
+
+// 位置positionの面までの部分
+fn top(cube: &Cube, direction: Direction, position: usize, m: &[i64]) -> i64 {
+    match direction {
+        Direction::R => m[index(position, cube.g1, cube.b1)] - m[index(position, cube.g1, cube.b0)] - m[index(position, cube.g0, cube.b1)] + m[index(position, cube.g0, cube.b0)],
+        Direction::G => m[index(cube.r1, position, cube.b1)] - m[index(cube.r1, position, cube.b0)] - m[index(cube.r0, position, cube.b1)] + m[index(cube.r0, position, cube.b0)],
+        Direction::B => m[index(cube.r1, cube.g1, position)] - m[index(cube.r1, cube.g0, position)] - m[index(cube.r0, cube.g1, position)] + m[index(cube.r0, cube.g0, position)],
+    }
+}
+
+// 箱の中の色の二乗誤差の合計
+fn variance(cube: &Cube, moments: &Moments) -> f64 {
+    let r = volume(cube, &moments.r) as f64;
+    let g = volume(cube, &moments.g) as f64;
+    let b = volume(cube, &moments.b) as f64;
+    volume(cube, &moments.square) - (r * r + g * g + b * b) / volume(cube, &moments.weight) as f64
+}
+
+// direction方向で分割した時に、2つの箱の (合計の二乗 / ピクセル数) の和が最大になる位置
+// 分割できない場合はNone
+fn maximize(cube: &Cube, direction: Direction, first: usize, last: usize, whole: [i64; 4], moments: &Moments) -> (f64, Option<usize>) {
+    let base = [
+        bottom(cube, direction, &moments.r),
+        bottom(cube, direction, &moments.g),
+        bottom(cube, direction, &moments.b),
+        bottom(cube, direction, &moments.weight),
+    ];
+    let mut max = 0.0;
+    let mut cut = None;
+    for position in first..last {
+        let half = [
+            base[0] + top(cube, direction, position, &moments.r),
+            base[1] + top(cube, direction, position, &moments.g),
+            base[2] + top(cube, direction, position, &moments.b),
+            base[3] + top(cube, direction, position, &moments.weight),
+        ];
+        if half[3] == 0 || half[3] == whole[3] {
+            continue;
+        }
+        let score = |h: [i64; 4]| ((h[0] as f64).powi(2) + (h[1] as f64).powi(2) + (h[2] as f64).powi(2)) / h[3] as f64;
+        let temp = score(half) + score([whole[0] - half[0], whole[1] - half[1], whole[2] - half[2], whole[3] - half[3]]);
+        if temp > max {
+            max = temp;
+            cut = Some(position);
+        }
+    }
+    (max, cut)
+}
+
+// 箱を2つに分割する(分割できない場合はNone)
+fn cut(cube: &mut Cube, moments: &Moments) -> Option<Cube> {
+    let whole = [
+        volume(cube, &moments.r),
+        volume(cube, &moments.g),
+        volume(cube, &moments.b),
+        volume(cube, &moments.weight),
+    ];
+    let (max_r, cut_r) = maximize(cube, Direction::R, cube.r0 + 1, cube.r1, whole, moments);
+    let (max_g, cut_g) = maximize(cube, Direction::G, cube.g0 + 1, cube.g1, whole, moments);
+    let (max_b, cut_b) = maximize(cube, Direction::B, cube.b0 + 1, cube.b1, whole, moments);
+
+    let mut next = Cube { r1: cube.r1, g1: cube.g1, b1: cube.b1, ..*cube };
+    if max_r >= max_g && max_r >= max_b {
+        let position = cut_r?;
+        next.r0 = position;
+        cube.r1 = position;
+    } else if max_g >= max_r && max_g >= max_b {
+        let position = cut_g?;
+        next.g0 = position;
+        cube.g1 = position;
+    } else {
+        let position = cut_b?;
+        next.b0 = position;
+        cube.b1 = position;
+    }
+    Some(next)
+}
+
+/// Wuの方法で減色したパレット(各色のピクセル数を含む)と、ヒストグラムの色ごとのパレットのインデックスを返す
+pub(crate) fn quantize(colors: &[Colors], size: usize) -> (Vec<Colors>, Vec<usize>) {
+    let moments = Moments::new(colors);
+    let mut cubes: Vec<Cube> = vec![Cube { r1: SIDE - 1, g1: SIDE - 1, b1: SIDE - 1, ..Cube::default() }];
+    let mut variances: Vec<f64> = vec![0.0];
+
+    // 二乗誤差が最大の箱から分割していく
+    let mut next = 0;
+    while cubes.len() < size.max(1) {
+        match cut(&mut cubes[next], &moments) {
+            Some(cube) => {
+                cubes.push(cube);
+                variances.push(0.0);
+                let last = cubes.len() - 1;
+                for i in [next, last] {
+                    let cube = &cubes[i];
+                    let cells = (cube.r1 - cube.r0) * (cube.g1 - cube.g0) * (cube.b1 - cube.b0);
+                    variances[i] = if cells > 1 { variance(cube, &moments) } else { 0.0 };
+                }
+            }
+            None => variances[next] = 0.0,
+        }
+        next = (0..cubes.len()).fold(0, |best, i| if variances[i] > variances[best] { i } else { best });
+        if variances[next] <= 0.0 {
+            break;
+        }
+    }
+
+    // 箱ごとの平均色をパレットにし、セルに箱のインデックスを付ける
+    let mut palette: Vec<Colors> = Vec::new();
+    let mut tags: Vec<usize> = vec![0; SIDE * SIDE * SIDE];
+    for cube in &cubes {
+        let weight = volume(cube, &moments.weight);
+        if weight == 0 {
+            continue;
+        }
+        let mean = |m: &[i64]| (volume(cube, m) as f64 / weight as f64).round() as u8;
+        for r in cube.r0 + 1..=cube.r1 {
+            for g in cube.g0 + 1..=cube.g1 {
+                for b in cube.b0 + 1..=cube.b1 {
+                    tags[index(r, g, b)] = palette.len();
+                }
+            }
+        }
+        palette.push(Colors::new(mean(&moments.r), mean(&moments.g), mean(&moments.b), mean(&moments.a), weight as u64));
+    }
+
+    let assignments = colors.iter().map(|color| {
+        let (r, g, b) = cell(color);
+        tags[index(r, g, b)]
+    }).collect();
+    (palette, assignments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantize() {
+        let colors: Vec<Colors> = vec![
+            Colors::new(0, 0, 0, 255, 2),
+            Colors::new(10, 0, 0, 255, 2),
+            Colors::new(255, 255, 255, 255, 1),
+            Colors::new(0, 0, 250, 255, 3),
+        ];
+        let (palette, assignments) = quantize(&colors, 3);
+        assert_eq!(palette.len(), 3);
+        assert_eq!(palette.iter().map(|color| color.count).sum::<u64>(), 8);
+        assert_eq!(palette[assignments[0]], Colors::new(5, 0, 0, 255, 4));
+        assert_eq!(assignments[0], assignments[1]);
+        assert_eq!(palette[assignments[2]], Colors::new(255, 255, 255, 255, 1));
+        assert_eq!(palette[assignments[3]], Colors::new(0, 0, 250, 255, 3));
+
+        // 色の種類より多く分割しない
+        let (palette, _) = quantize(&colors, 16);
+        assert_eq!(palette.len(), 4);
+    }
+
+    #[test]
+    fn test_moments() {
+        let colors: Vec<Colors> = vec![Colors::new(0, 0, 0, 255, 2), Colors::new(255, 255, 255, 255, 1)];
+        let moments = Moments::new(&colors);
+        let whole = Cube { r1: SIDE - 1, g1: SIDE - 1, b1: SIDE - 1, ..Cube::default() };
+        assert_eq!(volume(&whole, &moments.weight), 3);
+        assert_eq!(volume(&whole, &moments.r), 255);
+        assert!((variance(&whole, &moments) - 255.0 * 255.0 * 3.0 * 2.0 / 3.0).abs() < 0.01);
+    }
+}
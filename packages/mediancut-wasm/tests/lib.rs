@@ -31,60 +31,60 @@ fn test_calculate_count() {
     ];
     let result = mediancut_wasm::calculate_count(&image_data);
     assert_eq!(result, [
-        mediancut_wasm::Colors(229, 0, 25, 1),
-        mediancut_wasm::Colors(1, 230, 26, 1),
-        mediancut_wasm::Colors(1, 204, 50, 1),
-        mediancut_wasm::Colors(204, 0, 51, 1),
-        mediancut_wasm::Colors(204, 1, 51, 1),
-        mediancut_wasm::Colors(0, 204, 51, 1),
-        mediancut_wasm::Colors(178, 0, 76, 1),
-        mediancut_wasm::Colors(179, 0, 76, 1),
-        mediancut_wasm::Colors(179, 1, 76, 1),
-        mediancut_wasm::Colors(0, 179, 76, 1),
-        mediancut_wasm::Colors(1, 179, 76, 1),
-        mediancut_wasm::Colors(1, 180, 76, 1),
-        mediancut_wasm::Colors(154, 1, 101, 1),
-        mediancut_wasm::Colors(0, 153, 101, 2),
-        mediancut_wasm::Colors(153, 0, 102, 2),
-        mediancut_wasm::Colors(153, 1, 102, 1),
-        mediancut_wasm::Colors(0, 153, 102, 1),
-        mediancut_wasm::Colors(1, 153, 102, 1),
-        mediancut_wasm::Colors(127, 0, 127, 2),
-        mediancut_wasm::Colors(0, 128, 127, 5),
-        mediancut_wasm::Colors(128, 0, 128, 1),
-        mediancut_wasm::Colors(128, 1, 128, 2),
-        mediancut_wasm::Colors(0, 102, 152, 1),
-        mediancut_wasm::Colors(101, 0, 153, 5),
-        mediancut_wasm::Colors(102, 1, 153, 1),
-        mediancut_wasm::Colors(0, 102, 153, 2),
-        mediancut_wasm::Colors(0, 103, 153, 2),
-        mediancut_wasm::Colors(1, 103, 153, 1),
-        mediancut_wasm::Colors(0, 77, 178, 5),
-        mediancut_wasm::Colors(1, 77, 178, 2),
-        mediancut_wasm::Colors(75, 0, 179, 1),
-        mediancut_wasm::Colors(76, 0, 179, 3),
-        mediancut_wasm::Colors(76, 1, 179, 3),
-        mediancut_wasm::Colors(0, 51, 203, 4),
-        mediancut_wasm::Colors(1, 51, 203, 2),
-        mediancut_wasm::Colors(0, 52, 203, 1),
-        mediancut_wasm::Colors(50, 0, 204, 3),
-        mediancut_wasm::Colors(51, 0, 204, 1),
-        mediancut_wasm::Colors(50, 1, 204, 1),
-        mediancut_wasm::Colors(51, 1, 204, 1),
-        mediancut_wasm::Colors(0, 52, 204, 1),
-        mediancut_wasm::Colors(51, 1, 205, 2),
-        mediancut_wasm::Colors(0, 26, 229, 5),
-        mediancut_wasm::Colors(1, 26, 229, 3),
-        mediancut_wasm::Colors(1, 27, 229, 1),
-        mediancut_wasm::Colors(24, 0, 230, 1),
-        mediancut_wasm::Colors(25, 0, 230, 5),
-        mediancut_wasm::Colors(25, 1, 230, 3),
-        mediancut_wasm::Colors(3, 4, 246, 1),
-        mediancut_wasm::Colors(4, 4, 246, 4),
-        mediancut_wasm::Colors(4, 5, 246, 1),
-        mediancut_wasm::Colors(4, 4, 247, 1),
-        mediancut_wasm::Colors(4, 5, 247, 2),
-        mediancut_wasm::Colors(5, 5, 247, 1)
+        mediancut_wasm::Colors::new(229, 0, 25, 255, 1),
+        mediancut_wasm::Colors::new(1, 230, 26, 255, 1),
+        mediancut_wasm::Colors::new(1, 204, 50, 255, 1),
+        mediancut_wasm::Colors::new(204, 0, 51, 255, 1),
+        mediancut_wasm::Colors::new(204, 1, 51, 255, 1),
+        mediancut_wasm::Colors::new(0, 204, 51, 255, 1),
+        mediancut_wasm::Colors::new(178, 0, 76, 255, 1),
+        mediancut_wasm::Colors::new(179, 0, 76, 255, 1),
+        mediancut_wasm::Colors::new(179, 1, 76, 255, 1),
+        mediancut_wasm::Colors::new(0, 179, 76, 255, 1),
+        mediancut_wasm::Colors::new(1, 179, 76, 255, 1),
+        mediancut_wasm::Colors::new(1, 180, 76, 255, 1),
+        mediancut_wasm::Colors::new(154, 1, 101, 255, 1),
+        mediancut_wasm::Colors::new(0, 153, 101, 255, 2),
+        mediancut_wasm::Colors::new(153, 0, 102, 255, 2),
+        mediancut_wasm::Colors::new(153, 1, 102, 255, 1),
+        mediancut_wasm::Colors::new(0, 153, 102, 255, 1),
+        mediancut_wasm::Colors::new(1, 153, 102, 255, 1),
+        mediancut_wasm::Colors::new(127, 0, 127, 255, 2),
+        mediancut_wasm::Colors::new(0, 128, 127, 255, 5),
+        mediancut_wasm::Colors::new(128, 0, 128, 255, 1),
+        mediancut_wasm::Colors::new(128, 1, 128, 255, 2),
+        mediancut_wasm::Colors::new(0, 102, 152, 255, 1),
+        mediancut_wasm::Colors::new(101, 0, 153, 255, 5),
+        mediancut_wasm::Colors::new(102, 1, 153, 255, 1),
+        mediancut_wasm::Colors::new(0, 102, 153, 255, 2),
+        mediancut_wasm::Colors::new(0, 103, 153, 255, 2),
+        mediancut_wasm::Colors::new(1, 103, 153, 255, 1),
+        mediancut_wasm::Colors::new(0, 77, 178, 255, 5),
+        mediancut_wasm::Colors::new(1, 77, 178, 255, 2),
+        mediancut_wasm::Colors::new(75, 0, 179, 255, 1),
+        mediancut_wasm::Colors::new(76, 0, 179, 255, 3),
+        mediancut_wasm::Colors::new(76, 1, 179, 255, 3),
+        mediancut_wasm::Colors::new(0, 51, 203, 255, 4),
+        mediancut_wasm::Colors::new(1, 51, 203, 255, 2),
+        mediancut_wasm::Colors::new(0, 52, 203, 255, 1),
+        mediancut_wasm::Colors::new(50, 0, 204, 255, 3),
+        mediancut_wasm::Colors::new(51, 0, 204, 255, 1),
+        mediancut_wasm::Colors::new(50, 1, 204, 255, 1),
+        mediancut_wasm::Colors::new(51, 1, 204, 255, 1),
+        mediancut_wasm::Colors::new(0, 52, 204, 255, 1),
+        mediancut_wasm::Colors::new(51, 1, 205, 255, 2),
+        mediancut_wasm::Colors::new(0, 26, 229, 255, 5),
+        mediancut_wasm::Colors::new(1, 26, 229, 255, 3),
+        mediancut_wasm::Colors::new(1, 27, 229, 255, 1),
+        mediancut_wasm::Colors::new(24, 0, 230, 255, 1),
+        mediancut_wasm::Colors::new(25, 0, 230, 255, 5),
+        mediancut_wasm::Colors::new(25, 1, 230, 255, 3),
+        mediancut_wasm::Colors::new(3, 4, 246, 255, 1),
+        mediancut_wasm::Colors::new(4, 4, 246, 255, 4),
+        mediancut_wasm::Colors::new(4, 5, 246, 255, 1),
+        mediancut_wasm::Colors::new(4, 4, 247, 255, 1),
+        mediancut_wasm::Colors::new(4, 5, 247, 255, 2),
+        mediancut_wasm::Colors::new(5, 5, 247, 255, 1)
     ]
     );
 }
@@ -92,65 +92,82 @@ fn test_calculate_count() {
 #[test]
 fn test_average_color() {
     let colors: Vec<mediancut_wasm::Colors> = vec![
-        mediancut_wasm::Colors(229, 0, 25, 1),
-        mediancut_wasm::Colors(179, 0, 76, 1),
-        mediancut_wasm::Colors(127, 0, 127, 2),
-        mediancut_wasm::Colors(101, 0, 153, 5),
-        mediancut_wasm::Colors(75, 0, 179, 1),
-        mediancut_wasm::Colors(50, 0, 204, 3),
-        mediancut_wasm::Colors(204, 0, 51, 1),
-        mediancut_wasm::Colors(153, 0, 102, 2),
-        mediancut_wasm::Colors(25, 0, 230, 5),
-        mediancut_wasm::Colors(178, 0, 76, 1),
-        mediancut_wasm::Colors(128, 0, 128, 1),
-        mediancut_wasm::Colors(76, 0, 179, 3),
-        mediancut_wasm::Colors(51, 0, 204, 1),
-        mediancut_wasm::Colors(24, 0, 230, 1),
-        mediancut_wasm::Colors(204, 1, 51, 1),
-        mediancut_wasm::Colors(154, 1, 101, 1),
-        mediancut_wasm::Colors(25, 1, 230, 3),
-        mediancut_wasm::Colors(179, 1, 76, 1),
-        mediancut_wasm::Colors(76, 1, 179, 3),
-        mediancut_wasm::Colors(51, 1, 204, 1),
-        mediancut_wasm::Colors(153, 1, 102, 1),
-        mediancut_wasm::Colors(102, 1, 153, 1),
-        mediancut_wasm::Colors(51, 1, 205, 2),
-        mediancut_wasm::Colors(128, 1, 128, 2),
-        mediancut_wasm::Colors(50, 1, 204, 1),
-        mediancut_wasm::Colors(4, 4, 246, 4),
-        mediancut_wasm::Colors(4, 4, 247, 1),
-        mediancut_wasm::Colors(3, 4, 246, 1),
-        mediancut_wasm::Colors(4, 5, 247, 2),
-        mediancut_wasm::Colors(5, 5, 247, 1),
-        mediancut_wasm::Colors(4, 5, 246, 1),
-        mediancut_wasm::Colors(1, 26, 229, 3),
-        mediancut_wasm::Colors(0, 26, 229, 5),
-        mediancut_wasm::Colors(1, 27, 229, 1),
-        mediancut_wasm::Colors(1, 51, 203, 2),
-        mediancut_wasm::Colors(0, 51, 203, 4),
-        mediancut_wasm::Colors(0, 52, 204, 1),
-        mediancut_wasm::Colors(0, 52, 203, 1),
-        mediancut_wasm::Colors(0, 77, 178, 5),
-        mediancut_wasm::Colors(1, 77, 178, 2),
-        mediancut_wasm::Colors(0, 102, 153, 2),
-        mediancut_wasm::Colors(0, 102, 152, 1),
-        mediancut_wasm::Colors(0, 103, 153, 2),
-        mediancut_wasm::Colors(1, 103, 153, 1),
-        mediancut_wasm::Colors(0, 128, 127, 5),
-        mediancut_wasm::Colors(1, 153, 102, 1),
-        mediancut_wasm::Colors(0, 153, 102, 1),
-        mediancut_wasm::Colors(0, 153, 101, 2),
-        mediancut_wasm::Colors(0, 179, 76, 1),
-        mediancut_wasm::Colors(1, 179, 76, 1),
-        mediancut_wasm::Colors(1, 180, 76, 1),
-        mediancut_wasm::Colors(0, 204, 51, 1),
-        mediancut_wasm::Colors(1, 204, 50, 1),
-        mediancut_wasm::Colors(1, 230, 26, 1),
+        mediancut_wasm::Colors::new(229, 0, 25, 255, 1),
+        mediancut_wasm::Colors::new(179, 0, 76, 255, 1),
+        mediancut_wasm::Colors::new(127, 0, 127, 255, 2),
+        mediancut_wasm::Colors::new(101, 0, 153, 255, 5),
+        mediancut_wasm::Colors::new(75, 0, 179, 255, 1),
+        mediancut_wasm::Colors::new(50, 0, 204, 255, 3),
+        mediancut_wasm::Colors::new(204, 0, 51, 255, 1),
+        mediancut_wasm::Colors::new(153, 0, 102, 255, 2),
+        mediancut_wasm::Colors::new(25, 0, 230, 255, 5),
+        mediancut_wasm::Colors::new(178, 0, 76, 255, 1),
+        mediancut_wasm::Colors::new(128, 0, 128, 255, 1),
+        mediancut_wasm::Colors::new(76, 0, 179, 255, 3),
+        mediancut_wasm::Colors::new(51, 0, 204, 255, 1),
+        mediancut_wasm::Colors::new(24, 0, 230, 255, 1),
+        mediancut_wasm::Colors::new(204, 1, 51, 255, 1),
+        mediancut_wasm::Colors::new(154, 1, 101, 255, 1),
+        mediancut_wasm::Colors::new(25, 1, 230, 255, 3),
+        mediancut_wasm::Colors::new(179, 1, 76, 255, 1),
+        mediancut_wasm::Colors::new(76, 1, 179, 255, 3),
+        mediancut_wasm::Colors::new(51, 1, 204, 255, 1),
+        mediancut_wasm::Colors::new(153, 1, 102, 255, 1),
+        mediancut_wasm::Colors::new(102, 1, 153, 255, 1),
+        mediancut_wasm::Colors::new(51, 1, 205, 255, 2),
+        mediancut_wasm::Colors::new(128, 1, 128, 255, 2),
+        mediancut_wasm::Colors::new(50, 1, 204, 255, 1),
+        mediancut_wasm::Colors::new(4, 4, 246, 255, 4),
+        mediancut_wasm::Colors::new(4, 4, 247, 255, 1),
+        mediancut_wasm::Colors::new(3, 4, 246, 255, 1),
+        mediancut_wasm::Colors::new(4, 5, 247, 255, 2),
+        mediancut_wasm::Colors::new(5, 5, 247, 255, 1),
+        mediancut_wasm::Colors::new(4, 5, 246, 255, 1),
+        mediancut_wasm::Colors::new(1, 26, 229, 255, 3),
+        mediancut_wasm::Colors::new(0, 26, 229, 255, 5),
+        mediancut_wasm::Colors::new(1, 27, 229, 255, 1),
+        mediancut_wasm::Colors::new(1, 51, 203, 255, 2),
+        mediancut_wasm::Colors::new(0, 51, 203, 255, 4),
+        mediancut_wasm::Colors::new(0, 52, 204, 255, 1),
+        mediancut_wasm::Colors::new(0, 52, 203, 255, 1),
+        mediancut_wasm::Colors::new(0, 77, 178, 255, 5),
+        mediancut_wasm::Colors::new(1, 77, 178, 255, 2),
+        mediancut_wasm::Colors::new(0, 102, 153, 255, 2),
+        mediancut_wasm::Colors::new(0, 102, 152, 255, 1),
+        mediancut_wasm::Colors::new(0, 103, 153, 255, 2),
+        mediancut_wasm::Colors::new(1, 103, 153, 255, 1),
+        mediancut_wasm::Colors::new(0, 128, 127, 255, 5),
+        mediancut_wasm::Colors::new(1, 153, 102, 255, 1),
+        mediancut_wasm::Colors::new(0, 153, 102, 255, 1),
+        mediancut_wasm::Colors::new(0, 153, 101, 255, 2),
+        mediancut_wasm::Colors::new(0, 179, 76, 255, 1),
+        mediancut_wasm::Colors::new(1, 179, 76, 255, 1),
+        mediancut_wasm::Colors::new(1, 180, 76, 255, 1),
+        mediancut_wasm::Colors::new(0, 204, 51, 255, 1),
+        mediancut_wasm::Colors::new(1, 204, 50, 255, 1),
+        mediancut_wasm::Colors::new(1, 230, 26, 255, 1),
     ];
     let result = mediancut_wasm::average_color(colors.clone());
     assert_eq!(result, (42, 43, 170));
 }
 
+#[test]
+fn test_quantizer() {
+    // 左半分が赤系、右半分が青系の4x2
+    let image_data: Vec<u8> = vec![
+        250, 0, 0, 255, 240, 10, 0, 255, 0, 0, 250, 255, 0, 10, 240, 255,
+        250, 0, 0, 255, 240, 10, 0, 255, 0, 0, 250, 255, 0, 10, 240, 255,
+    ];
+    let histogram = mediancut_wasm::Histogram::from_rgba(&image_data);
+    let quantization = mediancut_wasm::Quantizer::new(2).quantize(&histogram);
+    assert_eq!(quantization.palette().colors(), [
+        mediancut_wasm::Colors::new(0, 5, 245, 255, 4),
+        mediancut_wasm::Colors::new(245, 5, 0, 255, 4),
+    ]);
+    assert_eq!(quantization.indices(&image_data), [1, 1, 0, 0, 1, 1, 0, 0]);
+    assert_eq!(&quantization.reduce(&image_data)[0..8], [245, 5, 0, 255, 245, 5, 0, 255]);
+}
+
 // #[test]
 // fn test_get_total_and_greatest_range_channel() {
 //     let colors: Vec<Colors> = vec![
@@ -18,6 +18,8 @@ type Cuboid = {
 };
 type Options = {
   strict: boolean;
+  // 分割する位置(median: 色の種類数の中央, population: ピクセル数の中央)
+  cutPosition?: "median" | "population";
 };
 
 /**
@@ -132,6 +134,31 @@ const getTotalAnGreatestRangeChannel = (
 };
 
 
+/**
+ * cuboidを分割する位置を取得
+ * 分割後のcuboidが空にならないように1〜length-1の範囲にする
+ * @param cuboid
+ * @param option
+ */
+export const cutIndex = (cuboid: Cuboid, option: Options): number => {
+  const len = cuboid.palettes.length;
+  if (option.cutPosition !== "population") {
+    return Math.floor((len + 1) / 2);
+  }
+
+  // 累積のピクセル数が半分を超える位置で分割する
+  let count = 0;
+  let index = len - 1;
+  for (let i = 0; i < len; i = (i + 1) | 0) {
+    count = count + cuboid.palettes[i][3];
+    if (count * 2 >= cuboid.total) {
+      index = i + 1;
+      break;
+    }
+  }
+  return Math.min(Math.max(index, 1), len - 1);
+};
+
 export const generateCuboid = (palettes: Palette[]): Cuboid => {
   const { total, channel, minR, minG, minB, maxR, maxG, maxB } =
     getTotalAnGreatestRangeChannel(palettes);
@@ -187,7 +214,7 @@ export const reduce = (
     // cuboid内の最大範囲の色チャンネルで並び替え
     const channel = targetCuboid.channel;
     targetCuboid.palettes.sort((a, b) => a[channel] - b[channel]);
-    const median = Math.floor((targetCuboid.palettes.length + 1) / 2);
+    const median = cutIndex(targetCuboid, option);
     // cuboidを分割
     const splitCuboid1 = generateCuboid(targetCuboid.palettes.slice(0, median));
     const splitCuboid2 = generateCuboid(targetCuboid.palettes.slice(median));
@@ -1,6 +1,6 @@
 import assert from "node:assert";
 import {test} from "node:test";
-import {averageColor, calculateColorCount} from "../lib/mediancut.cjs";
+import {averageColor, calculateColorCount, cutIndex, generateCuboid} from "../lib/mediancut.cjs";
 
 test("Calculate count", async (t) => {
   const palettes = calculateColorCount([
@@ -21,3 +21,18 @@ test("Average color", async (t) => {
   ]);
   assert.deepStrictEqual(average, [128, 64, 64]);
 });
+
+test("Cut index", async (t) => {
+  const cuboid = generateCuboid([
+    [0, 0, 0, 1],
+    [10, 0, 0, 1],
+    [20, 0, 0, 1],
+    [200, 0, 0, 10],
+    [250, 0, 0, 1],
+  ]);
+  assert.strictEqual(cutIndex(cuboid, { strict: true }), 3);
+  assert.strictEqual(
+    cutIndex(cuboid, { strict: true, cutPosition: "population" }),
+    4,
+  );
+});